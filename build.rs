@@ -1,9 +1,6 @@
 use anyhow::*;
-use fs_extra::copy_items;
-use fs_extra::dir::CopyOptions;
 use glob::glob;
 use std::fs::{read_to_string, write};
-use std::env;
 use std::path::PathBuf;
 
 struct ShaderData {
@@ -73,14 +70,5 @@ fn main() -> Result<()> {
         write(shader.spv_path, compiled.as_binary_u8())?;
     }
 
-    println!("cargo:rerun-if-changed=data/*");
-
-    let out_dir = env::var("OUT_DIR")?;
-    let mut copy_options = CopyOptions::new();
-    copy_options.overwrite = true;
-    let mut paths_to_copy = Vec::new();
-    paths_to_copy.push("data/");
-    copy_items(&paths_to_copy, out_dir, &copy_options)?;
-
     Ok(())
 }
@@ -1,11 +1,15 @@
 #version 450
 
 layout(location=0) in vec2 v_tex_coords;
+layout(location=1) in float v_highlight;
+layout(location=2) in vec4 v_color;
 layout(location=0) out vec4 f_color;
 
 layout(set = 0, binding = 0) uniform texture2D t_diffuse;
 layout(set = 0, binding = 1) uniform sampler s_diffuse;
 
 void main() {
-    f_color = texture(sampler2D(t_diffuse, s_diffuse), v_tex_coords);
+    // Textures have premultiplied alpha.
+    vec4 color = texture(sampler2D(t_diffuse, s_diffuse), v_tex_coords) * vec4(v_color.rgb * v_color.a, v_color.a);
+    f_color = vec4(mix(color.rgb, vec3(1.0, 0.6, 0.1) * color.a, 0.4 * v_highlight), color.a);
 }
@@ -3,19 +3,27 @@
 layout(location=0) in vec3 a_position;
 layout(location=1) in vec2 a_tex_coords;
 
+// Per instance.
+layout(location=2) in vec4 a_model_0;
+layout(location=3) in vec4 a_model_1;
+layout(location=4) in vec4 a_model_2;
+layout(location=5) in vec4 a_model_3;
+layout(location=6) in vec4 a_highlight;
+layout(location=7) in vec4 a_color;
+
 layout(location=0) out vec2 v_tex_coords;
+layout(location=1) out float v_highlight;
+layout(location=2) out vec4 v_color;
 
 layout(set=1, binding=0)
 uniform Uniforms {
     mat4 u_view_proj;
 };
 
-layout(set=2, binding=0)
-uniform MeshUniforms {
-    mat4 u_model;
-};
-
 void main() {
     v_tex_coords = a_tex_coords;
-    gl_Position = u_view_proj * u_model * vec4(a_position, 1.0);
+    v_highlight = a_highlight.x;
+    v_color = a_color;
+    mat4 model = mat4(a_model_0, a_model_1, a_model_2, a_model_3);
+    gl_Position = u_view_proj * model * vec4(a_position, 1.0);
 }
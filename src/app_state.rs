@@ -1,45 +1,101 @@
 use legion::*;
-use rapier2d::{dynamics::{BodyStatus, RigidBodyBuilder, RigidBodySet, JointSet}, geometry::{ColliderBuilder, ColliderSet}};
+use rapier2d::{
+    dynamics::{JointHandle, RigidBodySet, JointSet},
+    geometry::ColliderSet,
+};
 use crate::{
-    static_data::{DataAccessor, Component, RigidBodyStatus, Shape},
+    static_data::{DataAccessor, DataError, ValidationReport, Component, PhysicsSettings},
     asset::Assets,
     hierarchy::{Parent, Children},
     transform::{Transform2D, LocalTransform, GlobalTransform},
     events::Events,
-    command::Command,
-    texture::Texture,
+    command::{Command, SelectionMode},
+    history::{History, Edit, Transaction},
+    texture::{self, Texture},
     mesh,
     wgpu_state::WgpuState,
-    physics::{RigidBodyHandle, ColliderHandle},
+    physics::{self, RigidBodyHandle, ColliderHandles, GravityScale, JointMotors, PhysicsParameters, Simulation},
+    camera::Camera,
+    file_watcher::FileWatcher,
+    project::Project,
+    ui::UiFocus,
 };
 use std::{
-    collections::HashMap,
-    path::Path,
+    collections::{HashMap, HashSet},
+    ffi::OsStr,
+    path::{Path, PathBuf},
 };
 use smallvec::smallvec;
-use winit::event::{ElementState, KeyboardInput, VirtualKeyCode};
+use winit::event::{ElementState, KeyboardInput, ModifiersState, VirtualKeyCode};
 
 pub struct AppState {
     pub data_accessor: DataAccessor,
     entities: Vec<Entity>,
     entity_indices: HashMap<String, usize>,
     pub root_entities: Vec<Entity>,
+    joints: Vec<JointHandle>,
 
     pub current_collection: usize,
-    pub selected_scene: Option<(usize, usize)>,
+    pub selected_nodes: Vec<(usize, usize)>,
+
+    pub history: History,
+
+    // Render layers of the current collection that are hidden or can't be picked in the viewport.
+    pub hidden_layers: HashSet<usize>,
+    pub locked_layers: HashSet<usize>,
+
+    // Problems found while spawning the current scene, e.g. references that can't be resolved.
+    pub scene_problems: Vec<DataError>,
+
+    // Result of the last explicit validation of all collections.
+    pub validation_report: Option<ValidationReport>,
 }
 
+// The scene node of the current collection an entity was spawned for. Entities spawned from prefab children refer to
+// the node that instantiated the prefab.
+pub struct SourceNode(pub (usize, usize));
+
 impl AppState {
-    pub fn new() -> Self {
+    pub fn new(project: &Project) -> Self {
         let mut data_accessor = DataAccessor::new();
-        data_accessor.load_collections_in_directory(&Path::new("data"));
+        data_accessor.load_collections_in_directory(&project.data_directory);
         Self {
             data_accessor,
             entities: Vec::new(),
             entity_indices: HashMap::new(),
             root_entities: Vec::new(),
+            joints: Vec::new(),
             current_collection: 0,
-            selected_scene: None,
+            selected_nodes: Vec::new(),
+            history: History::new(),
+            hidden_layers: HashSet::new(),
+            locked_layers: HashSet::new(),
+            scene_problems: Vec::new(),
+            validation_report: None,
+        }
+    }
+
+    pub fn get_node_entity(&self, node: (usize, usize)) -> Option<Entity> {
+        if node.0 != self.current_collection {
+            return None;
+        }
+        let node_name = &self.data_accessor.collections[node.0].scene_nodes[node.1].name;
+        self.entity_indices.get(node_name).map(|entity_index| self.entities[*entity_index])
+    }
+
+    fn select(&mut self, nodes: &[(usize, usize)], mode: SelectionMode) {
+        if mode == SelectionMode::Replace {
+            self.selected_nodes.clear();
+        }
+        for node in nodes {
+            if let Some(index) = self.selected_nodes.iter().position(|selected_node| selected_node == node) {
+                if mode == SelectionMode::Toggle {
+                    self.selected_nodes.remove(index);
+                }
+            }
+            else {
+                self.selected_nodes.push(*node);
+            }
         }
     }
 }
@@ -51,7 +107,11 @@ pub fn handle_input(
     #[resource] wgpu_state: &mut WgpuState,
     #[resource] pipeline: &mesh::Pipeline,
     #[resource] textures: &mut Assets::<Texture>,*/
+    #[resource] app_state: &AppState,
     #[resource] input_events: &Events::<KeyboardInput>,
+    #[resource] modifiers: &ModifiersState,
+    #[resource] ui_focus: &UiFocus,
+    #[resource] commands: &mut Events::<Command>,
 ) {
     for event in &input_events.events {
         match event {
@@ -80,6 +140,31 @@ pub fn handle_input(
                             app_state.set_current_mesh_index(index, wgpu_state, pipeline, textures, command_buffer);*/
                         }
                     },
+                    VirtualKeyCode::Z => {
+                        if is_pressed && modifiers.ctrl() && !ui_focus.wants_keyboard {
+                            if modifiers.shift() {
+                                commands.send(Command::Redo);
+                            }
+                            else {
+                                commands.send(Command::Undo);
+                            }
+                        }
+                    },
+                    VirtualKeyCode::F => {
+                        if is_pressed && modifiers.is_empty() && !ui_focus.wants_keyboard {
+                            commands.send(Command::FrameSelection);
+                        }
+                    },
+                    VirtualKeyCode::Home => {
+                        if is_pressed && !ui_focus.wants_keyboard {
+                            commands.send(Command::FrameAll);
+                        }
+                    },
+                    VirtualKeyCode::S => {
+                        if is_pressed && modifiers.ctrl() && !ui_focus.wants_keyboard {
+                            commands.send(Command::SaveCollection(app_state.current_collection));
+                        }
+                    },
                     _ => {},
                 }
             },
@@ -95,8 +180,355 @@ pub fn handle_commands(world: &mut World, resources: &mut Resources) {
             Command::SetCurrentScene(index) => {
                 set_current_scene(*index, world, resources);
             }
+            Command::SaveCollection(index) => {
+                let app_state = resources.get::<AppState>().unwrap();
+                let mut file_watcher = resources.get_mut::<FileWatcher>().unwrap();
+                match app_state.data_accessor.save_collection(*index) {
+                    Ok(_) => {
+                        file_watcher.mark_unchanged(&app_state.data_accessor.collections[*index].path);
+                        info!("Saved collection {}", index);
+                    }
+                    Err(e) => error!("Failed to save collection {}: {:?}", index, e),
+                }
+            }
+            Command::Execute(transaction) => {
+                {
+                    let mut app_state = resources.get_mut::<AppState>().unwrap();
+                    let AppState { history, data_accessor, .. } = &mut *app_state;
+                    history.execute(transaction.clone(), data_accessor);
+                }
+                update_entities(transaction, world, resources);
+            }
+            Command::Undo => {
+                let transaction = {
+                    let mut app_state = resources.get_mut::<AppState>().unwrap();
+                    let AppState { history, data_accessor, .. } = &mut *app_state;
+                    history.undo(data_accessor).map(|transaction| transaction.inverse())
+                };
+                if let Some(transaction) = transaction {
+                    info!("Undo {}", transaction.name);
+                    update_entities(&transaction, world, resources);
+                }
+            }
+            Command::Redo => {
+                let transaction = {
+                    let mut app_state = resources.get_mut::<AppState>().unwrap();
+                    let AppState { history, data_accessor, .. } = &mut *app_state;
+                    history.redo(data_accessor).cloned()
+                };
+                if let Some(transaction) = transaction {
+                    info!("Redo {}", transaction.name);
+                    update_entities(&transaction, world, resources);
+                }
+            }
+            Command::Select(nodes, mode) => {
+                let mut app_state = resources.get_mut::<AppState>().unwrap();
+                app_state.select(nodes, *mode);
+            }
+            Command::Pick(position, mode) => {
+                let mut app_state = resources.get_mut::<AppState>().unwrap();
+                let picked_node = pick_node(*position, &app_state.locked_layers, world);
+                match picked_node {
+                    Some(node) => app_state.select(&[node], *mode),
+                    None if *mode == SelectionMode::Replace => app_state.selected_nodes.clear(),
+                    None => {}
+                }
+            }
+            Command::FrameSelection => {
+                let selected_nodes = resources.get::<AppState>().unwrap().selected_nodes.clone();
+                if let Some((min, max)) = get_bounds(world, |source_node| selected_nodes.contains(&source_node.0)) {
+                    resources.get_mut::<Camera>().unwrap().frame(min, max);
+                }
+            }
+            Command::FrameAll => {
+                if let Some((min, max)) = get_bounds(world, |_| true) {
+                    resources.get_mut::<Camera>().unwrap().frame(min, max);
+                }
+            }
+            Command::ReloadFiles { changed, removed } => {
+                reload_files(changed, removed, world, resources);
+            }
+            Command::SetLayerHidden(layer, is_hidden) => {
+                let mut app_state = resources.get_mut::<AppState>().unwrap();
+                if *is_hidden {
+                    app_state.hidden_layers.insert(*layer);
+                }
+                else {
+                    app_state.hidden_layers.remove(layer);
+                }
+            }
+            Command::SetLayerLocked(layer, is_locked) => {
+                let mut app_state = resources.get_mut::<AppState>().unwrap();
+                if *is_locked {
+                    app_state.locked_layers.insert(*layer);
+                }
+                else {
+                    app_state.locked_layers.remove(layer);
+                }
+            }
+            Command::ValidateCollections => {
+                let mut app_state = resources.get_mut::<AppState>().unwrap();
+                let report = app_state.data_accessor.validate();
+                info!("Validated collections, found {} problems", report.problems.len());
+                app_state.validation_report = Some(report);
+            }
+            Command::PlaySimulation => {
+                let rigid_body_set = resources.get::<RigidBodySet>().unwrap();
+                resources.get_mut::<Simulation>().unwrap().play(&rigid_body_set);
+            }
+            Command::PauseSimulation => {
+                resources.get_mut::<Simulation>().unwrap().pause();
+            }
+            Command::StepSimulation => {
+                let rigid_body_set = resources.get::<RigidBodySet>().unwrap();
+                resources.get_mut::<Simulation>().unwrap().step(&rigid_body_set);
+            }
+            Command::ResetSimulation => {
+                let mut rigid_body_set = resources.get_mut::<RigidBodySet>().unwrap();
+                resources.get_mut::<Simulation>().unwrap().reset(&mut rigid_body_set);
+            }
+        }
+    }
+}
+
+fn reload_files(paths: &[PathBuf], removed_paths: &[PathBuf], world: &mut World, resources: &mut Resources) {
+    let mut needs_respawn = false;
+    {
+        let mut app_state = resources.get_mut::<AppState>().unwrap();
+        let wgpu_state = resources.get::<WgpuState>().unwrap();
+        let mut textures = resources.get_mut::<Assets<Texture>>().unwrap();
+
+        let current_collection = app_state.current_collection;
+        let selected_names: Vec<String> = app_state.selected_nodes.iter()
+            .map(|node| app_state.data_accessor.collections[node.0].scene_nodes[node.1].name.clone())
+            .collect();
+
+        let mut has_reloaded_collection = false;
+        for path in paths {
+            if path.extension() == Some(OsStr::new("ron")) {
+                match app_state.data_accessor.reload_collection(path) {
+                    Ok(_) => {
+                        info!("Reloaded {}", path.display());
+                        has_reloaded_collection = true;
+                    }
+                    Err(e) => error!("Failed to reload {}", e),
+                }
+            }
+            else if path.extension() == Some(OsStr::new("json")) {
+                let atlas_index = match app_state.data_accessor.reload_atlas(path) {
+                    Ok(index) => index,
+                    Err(e) => {
+                        error!("Failed to reload {}", e);
+                        continue;
+                    }
+                };
+                info!("Reloaded {}", path.display());
+                let atlas_name = app_state.data_accessor.atlases[atlas_index].name.clone();
+                has_reloaded_collection |= reload_sprite_collections(&mut app_state.data_accessor, &atlas_name);
+            }
+            else if let Some(image_path) = get_reloaded_image_path(path) {
+                match Texture::reload(&mut textures, &wgpu_state.device, &wgpu_state.queue, &image_path) {
+                    Ok(true) => {
+                        info!("Reloaded {}", path.display());
+                        needs_respawn = true;
+                    }
+                    Ok(false) => {}
+                    Err(e) => error!("Failed to reload {}: {:?}", path.display(), e),
+                }
+            }
+        }
+        for path in removed_paths {
+            if path.extension() == Some(OsStr::new("ron")) {
+                if app_state.data_accessor.flag_removed_collection(path) {
+                    warn!("{} was removed, its collection stays loaded until the file is written again", path.display());
+                }
+            }
+            else if path.extension() == Some(OsStr::new("json")) {
+                let atlas_name = path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+                if app_state.data_accessor.unload_atlas(&atlas_name).is_some() {
+                    info!("Unloaded {}", path.display());
+                    has_reloaded_collection |= reload_sprite_collections(&mut app_state.data_accessor, &atlas_name);
+                }
+            }
+            else if let Some(image_path) = get_reloaded_image_path(path) {
+                // Without its import settings the image is loaded with the default ones. Meshes report removed images
+                // when they are spawned again.
+                if image_path.is_file() {
+                    match Texture::reload(&mut textures, &wgpu_state.device, &wgpu_state.queue, &image_path) {
+                        Ok(reloaded) => needs_respawn |= reloaded,
+                        Err(e) => error!("Failed to reload {}: {:?}", image_path.display(), e),
+                    }
+                }
+                else {
+                    needs_respawn = true;
+                }
+            }
+        }
+
+        if has_reloaded_collection {
+            // Node indices may have changed, so the history can't be applied anymore and the selection is restored by
+            // name. Layers may have changed as well.
+            app_state.history.clear();
+            app_state.hidden_layers.clear();
+            app_state.locked_layers.clear();
+            let collection = &app_state.data_accessor.collections[current_collection];
+            let selected_nodes = selected_names.iter()
+                .filter_map(|name| collection.scene_node_indices.get(name))
+                .map(|index| (current_collection, *index))
+                .collect();
+            app_state.selected_nodes = selected_nodes;
+            needs_respawn = true;
+        }
+    }
+
+    // Respawning rebuilds the meshes, so they pick up reloaded textures as well.
+    if needs_respawn {
+        respawn_current_scene(world, resources);
+    }
+}
+
+// Collections generate their sprites when they are loaded, so those that use the atlas are loaded again. Returns
+// whether any collection was reloaded.
+fn reload_sprite_collections(data_accessor: &mut DataAccessor, atlas_name: &str) -> bool {
+    let collection_paths: Vec<PathBuf> = data_accessor.collections.iter()
+        .filter(|collection| collection.sprites.iter().any(|sprite| sprite.atlas == atlas_name))
+        .map(|collection| collection.path.clone())
+        .collect();
+    let mut has_reloaded_collection = false;
+    for collection_path in collection_paths {
+        match data_accessor.reload_collection(&collection_path) {
+            Ok(_) => has_reloaded_collection = true,
+            Err(e) => error!("Failed to reload {}", e),
         }
     }
+    has_reloaded_collection
+}
+
+// Changed import settings reload the image they belong to.
+fn get_reloaded_image_path(path: &Path) -> Option<PathBuf> {
+    let image_path = if path.extension() == Some(OsStr::new(texture::SETTINGS_EXTENSION)) {
+        path.with_extension("")
+    }
+    else {
+        path.to_path_buf()
+    };
+    image::ImageFormat::from_path(&image_path).ok().map(|_| image_path)
+}
+
+// World space bounds of all entities accepted by the filter. Entities without a mesh count as a point.
+fn get_bounds<F>(world: &World, filter: F) -> Option<(glam::Vec2, glam::Vec2)>
+where
+    F: Fn(&SourceNode) -> bool,
+{
+    let mut bounds: Option<(glam::Vec2, glam::Vec2)> = None;
+    let mut add_point = |point: glam::Vec2| {
+        bounds = Some(match bounds {
+            Some((min, max)) => (min.min(point), max.max(point)),
+            None => (point, point),
+        });
+    };
+
+    let mut query = <(&GlobalTransform, &SourceNode, Option<&mesh::Mesh>)>::query();
+    for (transform, source_node, mesh) in query.iter(world) {
+        if !filter(source_node) {
+            continue;
+        }
+        let matrix = transform.0.build_matrix();
+        match mesh {
+            Some(mesh) => {
+                for position in &mesh.positions {
+                    add_point(matrix.transform_point3(position.extend(0.0)).truncate());
+                }
+            }
+            None => add_point(transform.0.translation.truncate()),
+        }
+    }
+    bounds
+}
+
+// Edits of nodes in the current collection that only change their transform or color are applied to the spawned
+// entities directly. Everything else respawns the scene.
+enum EntityUpdate {
+    Transform(Transform2D),
+    Color([f32; 4]),
+}
+
+fn update_entities(transaction: &Transaction, world: &mut World, resources: &mut Resources) {
+    let entity_updates = {
+        let app_state = resources.get::<AppState>().unwrap();
+        get_entity_updates(transaction, &app_state)
+    };
+    let entity_updates = match entity_updates {
+        Some(entity_updates) => entity_updates,
+        None => {
+            respawn_current_scene(world, resources);
+            return;
+        }
+    };
+
+    let mut rigid_body_set = resources.get_mut::<RigidBodySet>().unwrap();
+    let mut simulation = resources.get_mut::<Simulation>().unwrap();
+    let pixels_per_meter = resources.get::<PhysicsParameters>().unwrap().pixels_per_meter;
+    for (entity, update) in entity_updates {
+        if let Some(mut entry) = world.entry(entity) {
+            match update {
+                EntityUpdate::Transform(transform) => {
+                    if let Ok(local_transform) = entry.get_component_mut::<LocalTransform>() {
+                        local_transform.0 = transform.clone();
+                    }
+                    if let Ok(rigid_body_handle) = entry.get_component::<RigidBodyHandle>() {
+                        if let Some(rigid_body) = rigid_body_set.get_mut(rigid_body_handle.0) {
+                            let translation = (transform.translation.x, transform.translation.y);
+                            let position = physics::get_isometry(translation, transform.rotation, pixels_per_meter);
+                            rigid_body.set_position(position, true);
+                            simulation.set_snapshot_position(rigid_body_handle.0, position);
+                        }
+                    }
+                }
+                EntityUpdate::Color(color) => {
+                    if let Ok(params) = entry.get_component_mut::<mesh::PipelineParams>() {
+                        params.set_color(color);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn get_entity_updates(transaction: &Transaction, app_state: &AppState) -> Option<Vec<(Entity, EntityUpdate)>> {
+    let mut entity_updates = Vec::new();
+    for edit in &transaction.edits {
+        match edit {
+            Edit::SetComponent { node, new: Some(Component::Transform { translation, rotation }), .. } => {
+                let entity = app_state.get_node_entity(*node)?;
+                let transform = Transform2D::new(&glam::Vec3::new(translation.0, translation.1, 0.0), *rotation);
+                entity_updates.push((entity, EntityUpdate::Transform(transform)));
+            }
+            Edit::SetComponent { node, new: Some(Component::Color(r, g, b, a)), .. } => {
+                let entity = app_state.get_node_entity(*node)?;
+                entity_updates.push((entity, EntityUpdate::Color([*r, *g, *b, *a])));
+            }
+            _ => return None,
+        }
+    }
+    Some(entity_updates)
+}
+
+fn pick_node(world_position: glam::Vec2, locked_layers: &HashSet<usize>, world: &World) -> Option<(usize, usize)> {
+    // The hit that was drawn last is the one on top. Hidden meshes aren't drawn, and locked ones are skipped.
+    let mut picked_node = None;
+    let mut query = <(&GlobalTransform, &mesh::Mesh, &mesh::PipelineParams, &SourceNode)>::query();
+    for (transform, mesh, params, source_node) in query.iter(world) {
+        let draw_index = match params.draw_index {
+            Some(draw_index) if !locked_layers.contains(&params.layer) => draw_index,
+            _ => continue,
+        };
+        let is_on_top = picked_node.is_none_or(|(picked_draw_index, _)| draw_index > picked_draw_index);
+        if is_on_top && mesh.contains_point(transform.0.inverse_transform_point(world_position)) {
+            picked_node = Some((draw_index, source_node.0));
+        }
+    }
+    picked_node.map(|(_, node)| node)
 }
 
 fn remove_all_entities(world: &mut World, resources: &mut Resources) {
@@ -105,13 +537,20 @@ fn remove_all_entities(world: &mut World, resources: &mut Resources) {
     let mut collider_set = resources.get_mut::<ColliderSet>().unwrap();
     let mut joint_set = resources.get_mut::<JointSet>().unwrap();
 
+    // The snapshot refers to the rigid bodies that are removed.
+    resources.get_mut::<Simulation>().unwrap().discard_snapshot();
+    for joint_handle in app_state.joints.drain(..) {
+        joint_set.remove(joint_handle, &mut rigid_body_set, false);
+    }
     for entity in app_state.entities.drain(..) {
         if let Some(entry) = world.entry(entity) {
             if let Ok(rigid_body_handle) = entry.get_component::<RigidBodyHandle>() {
                 rigid_body_set.remove(rigid_body_handle.0, &mut collider_set, &mut joint_set);
             }
-            if let Ok(collider_handle) = entry.get_component::<ColliderHandle>() {
-                collider_set.remove(collider_handle.0, &mut rigid_body_set, false);
+            if let Ok(collider_handles) = entry.get_component::<ColliderHandles>() {
+                for collider_handle in &collider_handles.0 {
+                    collider_set.remove(*collider_handle, &mut rigid_body_set, false);
+                }
             }
         }
         world.remove(entity);
@@ -123,16 +562,41 @@ fn remove_all_entities(world: &mut World, resources: &mut Resources) {
 
 fn set_current_scene(index: usize, world: &mut World, resources: &mut Resources) {
     remove_all_entities(world, resources);
+    // Mesh buffers are identified by names relative to the collection, and the data may have been reloaded.
+    resources.get_mut::<Assets<mesh::MeshBuffers>>().unwrap().clear();
 
     let mut root_nodes = Vec::new();
     {
         let mut app_state = resources.get_mut::<AppState>().unwrap();
+        app_state.scene_problems.clear();
+        if index >= app_state.data_accessor.collections.len() {
+            return;
+        }
+        if app_state.current_collection != index {
+            app_state.selected_nodes.clear();
+            app_state.hidden_layers.clear();
+            app_state.locked_layers.clear();
+        }
         app_state.current_collection = index;
         
-        let collection = &app_state.data_accessor.collections[index];
+        let AppState { data_accessor, scene_problems, .. } = &mut *app_state;
+        let collection = &data_accessor.collections[index];
 
         info!("Set current collection to {}", index);
 
+        // The settings are needed to spawn the rigid bodies, so invalid ones are replaced by the defaults.
+        let physics_settings = match collection.physics_settings.check() {
+            Ok(_) => collection.physics_settings.clone(),
+            Err(message) => {
+                scene_problems.push(DataError::InvalidPhysicsSettings {
+                    collection: collection.name.clone(),
+                    message,
+                });
+                PhysicsSettings::default()
+            }
+        };
+        *resources.get_mut::<PhysicsParameters>().unwrap() = PhysicsParameters::new(&physics_settings);
+
         for (i, node) in collection.scene_nodes.iter().enumerate() {
             if let None = node.get_parent() {
                 root_nodes.push(i);
@@ -141,111 +605,322 @@ fn set_current_scene(index: usize, world: &mut World, resources: &mut Resources)
     }
 
     for node_index in root_nodes {
-        spawn_entities_recursive(node_index, index, index, None, world, resources);
+        let mut context = SpawnContext { collection_to_spawn_in: index, ancestors: Vec::new() };
+        spawn_entities_recursive(node_index, index, None, (index, node_index), &mut context, world, resources);
     }
+    spawn_joints(world, resources);
+}
+
+// Joints are spawned after all entities, since they can connect any two of them.
+fn spawn_joints(world: &mut World, resources: &mut Resources) {
+    let mut app_state = resources.get_mut::<AppState>().unwrap();
+    let mut rigid_body_set = resources.get_mut::<RigidBodySet>().unwrap();
+    let mut joint_set = resources.get_mut::<JointSet>().unwrap();
+    let pixels_per_meter = resources.get::<PhysicsParameters>().unwrap().pixels_per_meter;
+
+    let AppState { data_accessor, scene_problems, entities, entity_indices, joints, current_collection, .. } = &mut *app_state;
+    let collection = &data_accessor.collections[*current_collection];
+    for joint in &collection.joints {
+        if let Err(message) = joint.check() {
+            scene_problems.push(DataError::InvalidComponent {
+                collection: collection.name.clone(),
+                node: joint.name.clone(),
+                message,
+            });
+            continue;
+        }
+
+        let mut get_rigid_body_handle = |node_name: &String| {
+            let entity = match entity_indices.get(node_name) {
+                Some(entity_index) => entities[*entity_index],
+                None => {
+                    scene_problems.push(DataError::DanglingReference {
+                        collection: collection.name.clone(),
+                        node: joint.name.clone(),
+                        kind: "Joint node",
+                        reference: node_name.clone(),
+                    });
+                    return None;
+                }
+            };
+            let rigid_body_handle = world.entry(entity)
+                .and_then(|entry| entry.get_component::<RigidBodyHandle>().ok().map(|handle| handle.0));
+            if rigid_body_handle.is_none() {
+                scene_problems.push(DataError::InvalidComponent {
+                    collection: collection.name.clone(),
+                    node: joint.name.clone(),
+                    message: format!("Joint node {} has no RigidBody", node_name),
+                });
+            }
+            rigid_body_handle.map(|handle| (entity, handle))
+        };
+        let ((_, body1), (entity2, body2)) = match (get_rigid_body_handle(&joint.node1), get_rigid_body_handle(&joint.node2)) {
+            (Some(node1), Some(node2)) => (node1, node2),
+            _ => continue,
+        };
+
+        let (params, motor) = physics::build_joint(joint, body1, body2, pixels_per_meter);
+        joints.push(joint_set.insert(&mut rigid_body_set, body1, body2, params));
+        if let Some(motor) = motor {
+            let mut entry = world.entry(entity2).unwrap();
+            if let Ok(joint_motors) = entry.get_component_mut::<JointMotors>() {
+                joint_motors.0.push(motor);
+            }
+            else {
+                entry.add_component(JointMotors(vec![motor]));
+            }
+        }
+    }
+}
+
+fn respawn_current_scene(world: &mut World, resources: &mut Resources) {
+    let current_collection = resources.get::<AppState>().unwrap().current_collection;
+    set_current_scene(current_collection, world, resources);
+}
+
+struct SpawnContext {
+    // Nodes and prefabs are looked up from the collection whose scene is spawned.
+    collection_to_spawn_in: usize,
+
+    // The prefab nodes currently being spawned, to detect cycles.
+    ancestors: Vec<(usize, usize)>,
 }
 
 fn spawn_entities_recursive(
     index: usize,
     source_collection: usize,
-    collection_to_spawn_in: usize,
     parent_name: Option<String>,
+    source_node: (usize, usize),
+    context: &mut SpawnContext,
     world: &mut World,
     resources: &mut Resources
 ) {
-    let (entity_name, children) = {
+    let collection_to_spawn_in = context.collection_to_spawn_in;
+    let spawned = {
         let mut app_state = resources.get_mut::<AppState>().unwrap();
         let wgpu_state = resources.get::<WgpuState>().unwrap();
         let pipeline = resources.get_mut::<mesh::Pipeline>().unwrap();
         let mut textures = resources.get_mut::<Assets<Texture>>().unwrap();
+        let mut mesh_buffers = resources.get_mut::<Assets<mesh::MeshBuffers>>().unwrap();
         let mut rigid_body_set = resources.get_mut::<RigidBodySet>().unwrap();
         let mut collider_set = resources.get_mut::<ColliderSet>().unwrap();
+        let pixels_per_meter = resources.get::<PhysicsParameters>().unwrap().pixels_per_meter;
 
-        let (node, children) = {
-            let collection = &app_state.data_accessor.collections[source_collection];
-            let node_name = &collection.scene_nodes[index].name;
-            app_state.data_accessor.build_node(node_name, collection_to_spawn_in).unwrap()
-        };
+        let AppState { data_accessor, scene_problems, .. } = &mut *app_state;
+        let collection_name = data_accessor.collections[source_collection].name.clone();
+        let node_name = data_accessor.collections[source_collection].scene_nodes[index].name.clone();
+        let qualified_node_name = data_accessor.get_qualified_node_name((source_collection, index), collection_to_spawn_in);
+        match data_accessor.build_node(&qualified_node_name, collection_to_spawn_in) {
+            Ok((node, children)) => {
+                let source_node = if source_collection == collection_to_spawn_in {
+                    (source_collection, index)
+                } else {
+                    source_node
+                };
+                let entity = world.push((SourceNode(source_node),));
+                let mut entry = world.entry(entity).unwrap();
 
-        let entity = world.push(());
-        let mut entry = world.entry(entity).unwrap();
-
-        let mut transform = None;
-        let mut rigid_body_handle = None;
-
-        // Components are sorted, so that they get created in the right order.
-        for component in &node.components {
-            match component {
-                Component::Transform{ translation, rotation } => {
-                    let new_transform = Transform2D::new(&glam::Vec3::new(translation.0, translation.1, 0.0), *rotation);
-                    transform = Some(new_transform.clone());
-                    entry.add_component(LocalTransform(new_transform.clone()));
-                    entry.add_component(GlobalTransform(new_transform.clone()));
-                }
-                Component::Mesh(mesh_name) => {
-                    let transform = transform.as_ref().unwrap();
-                    let mesh_data = app_state.data_accessor.get_mesh(mesh_name, collection_to_spawn_in).unwrap();
-                    let mesh = mesh::Mesh::new(&wgpu_state, &pipeline, &mut textures, mesh_data);
-                    entry.add_component(mesh);
-                    let params = mesh::PipelineParams::new(&wgpu_state, &pipeline, &transform.build_matrix());
-                    entry.add_component(params);
-                }
-                Component::RigidBody(name) => {
-                    let transform = transform.as_ref().unwrap();
-                    let rigid_body_data = app_state.data_accessor.get_rigid_body(name, collection_to_spawn_in).unwrap();
-                    let status = match rigid_body_data.status {
-                        RigidBodyStatus::Static => BodyStatus::Static,
-                        RigidBodyStatus::Dynamic => BodyStatus::Dynamic,
-                        RigidBodyStatus::Kinematic => BodyStatus::Kinematic,
-                    };
-                    let rigid_body =
-                        RigidBodyBuilder::new(status).translation(transform.translation.x / 64.0, transform.translation.y / 64.0).build();
-                    let new_rigid_body_handle = rigid_body_set.insert(rigid_body);
-                    rigid_body_handle = Some(new_rigid_body_handle);
-                    entry.add_component(RigidBodyHandle(new_rigid_body_handle));
-                }
-                Component::Collider(name) => {
-                    let collider_data = app_state.data_accessor.get_collider(name, collection_to_spawn_in).unwrap();
-                    if let Shape::Cuboid(hx, hy) = collider_data.shape {
-                        let collider = ColliderBuilder::cuboid(hx / 64.0, hy / 64.0).build();
-                        let collider_handle = collider_set.insert(collider, rigid_body_handle.unwrap(), &mut rigid_body_set);
-                        entry.add_component(ColliderHandle(collider_handle));
+                let mut transform = None;
+                let mut rigid_body_handle = None;
+                let dangling_reference = |kind: &'static str, reference: &String| DataError::DanglingReference {
+                    collection: collection_name.clone(),
+                    node: node_name.clone(),
+                    kind,
+                    reference: reference.clone(),
+                };
+                let invalid_component = |message: String| DataError::InvalidComponent {
+                    collection: collection_name.clone(),
+                    node: node_name.clone(),
+                    message,
+                };
+
+                // Components are sorted, so that they get created in the right order.
+                for component in &node.components {
+                    match component {
+                        Component::Transform{ translation, rotation } => {
+                            let new_transform = Transform2D::new(&glam::Vec3::new(translation.0, translation.1, 0.0), *rotation);
+                            transform = Some(new_transform.clone());
+                            entry.add_component(LocalTransform(new_transform.clone()));
+                            entry.add_component(GlobalTransform(new_transform.clone()));
+                        }
+                        Component::Mesh(mesh_name) => {
+                            let transform = match transform.as_ref() {
+                                Some(transform) => transform,
+                                None => {
+                                    scene_problems.push(invalid_component("Mesh requires a Transform".to_string()));
+                                    continue;
+                                }
+                            };
+                            let mesh_data = match data_accessor.get_mesh(mesh_name, collection_to_spawn_in) {
+                                Some(mesh_data) => mesh_data,
+                                None => {
+                                    scene_problems.push(dangling_reference("Mesh", mesh_name));
+                                    continue;
+                                }
+                            };
+                            if let Err(message) = mesh_data.check() {
+                                scene_problems.push(DataError::InvalidMesh {
+                                    collection: collection_name.clone(),
+                                    mesh: mesh_name.clone(),
+                                    message,
+                                });
+                                continue;
+                            }
+                            let texture_path = data_accessor.get_texture_path(&mesh_data.texture);
+                            if !texture_path.is_file() {
+                                scene_problems.push(DataError::MissingTexture {
+                                    collection: collection_name.clone(),
+                                    mesh: mesh_name.clone(),
+                                    path: texture_path,
+                                });
+                                continue;
+                            }
+                            match mesh::Mesh::new(&wgpu_state, &pipeline, &mut textures, &mut mesh_buffers, mesh_name, mesh_data, &texture_path) {
+                                Ok(mesh) => {
+                                    entry.add_component(mesh);
+                                    entry.add_component(mesh::PipelineParams::new(&transform.build_matrix()));
+                                }
+                                Err(e) => scene_problems.push(invalid_component(format!("Failed to create mesh {}: {}", mesh_name, e))),
+                            }
+                        }
+                        Component::RigidBody(name) => {
+                            let transform = match transform.as_ref() {
+                                Some(transform) => transform,
+                                None => {
+                                    scene_problems.push(invalid_component("RigidBody requires a Transform".to_string()));
+                                    continue;
+                                }
+                            };
+                            let rigid_body_data = match data_accessor.get_rigid_body(name, collection_to_spawn_in) {
+                                Some(rigid_body_data) => rigid_body_data,
+                                None => {
+                                    scene_problems.push(dangling_reference("RigidBody", name));
+                                    continue;
+                                }
+                            };
+                            if let Err(message) = rigid_body_data.check() {
+                                scene_problems.push(invalid_component(format!("RigidBody {}: {}", name, message)));
+                                continue;
+                            }
+                            if rigid_body_data.ccd {
+                                warn!("{}/{}: Continuous collision detection is not supported", collection_name, name);
+                            }
+                            let rigid_body = physics::build_rigid_body(rigid_body_data, transform, pixels_per_meter).build();
+                            let new_rigid_body_handle = rigid_body_set.insert(rigid_body);
+                            rigid_body_handle = Some((new_rigid_body_handle, rigid_body_data));
+                            entry.add_component(RigidBodyHandle(new_rigid_body_handle));
+                            if rigid_body_data.gravity_scale != 1.0 {
+                                entry.add_component(GravityScale(rigid_body_data.gravity_scale));
+                            }
+                        }
+                        Component::Collider(name) => {
+                            let collider_data = match data_accessor.get_collider(name, collection_to_spawn_in) {
+                                Some(collider_data) => collider_data,
+                                None => {
+                                    scene_problems.push(dangling_reference("Collider", name));
+                                    continue;
+                                }
+                            };
+                            let (rigid_body_handle, rigid_body_data) = match rigid_body_handle {
+                                Some(rigid_body_handle) => rigid_body_handle,
+                                None => {
+                                    scene_problems.push(invalid_component("Collider requires a RigidBody".to_string()));
+                                    continue;
+                                }
+                            };
+                            if let Err(message) = collider_data.check() {
+                                scene_problems.push(invalid_component(format!("Collider {}: {}", name, message)));
+                                continue;
+                            }
+                            let collider_handles = physics::build_colliders(collider_data, pixels_per_meter).iter()
+                                .map(|builder| collider_set.insert(builder.build(), rigid_body_handle, &mut rigid_body_set))
+                                .collect();
+                            if let Some(rigid_body) = rigid_body_set.get_mut(rigid_body_handle) {
+                                physics::add_polygon_mass(rigid_body, rigid_body_data, collider_data, pixels_per_meter);
+                            }
+                            entry.add_component(ColliderHandles(collider_handles));
+                        }
+                        Component::Layer { name, order } => {
+                            let layer = match data_accessor.collections[collection_to_spawn_in].get_render_layer_index(name) {
+                                Some(layer) => layer,
+                                None => {
+                                    scene_problems.push(dangling_reference("Layer", name));
+                                    continue;
+                                }
+                            };
+                            if let Ok(params) = entry.get_component_mut::<mesh::PipelineParams>() {
+                                params.layer = layer;
+                                params.order = *order;
+                            }
+                        }
+                        Component::Color(r, g, b, a) => {
+                            if let Ok(params) = entry.get_component_mut::<mesh::PipelineParams>() {
+                                params.set_color([*r, *g, *b, *a]);
+                            }
+                        }
+                        _ => {}
                     }
                 }
-                _ => {}
-            }
-        }
 
-        let entity_name = if source_collection == collection_to_spawn_in {
-            node.name.clone()
-        } else {
-            let mut name = parent_name.as_ref().unwrap().clone();
-            name.push_str(&node.name);
-            name
-        };
-        let entity_index = app_state.entities.len();
-        app_state.entity_indices.insert(entity_name.clone(), entity_index);
-        app_state.entities.push(entity);
-
-        if let Some(parent_name) = parent_name {
-            let parent_index = app_state.entity_indices.get(&parent_name).unwrap();
-            let parent = app_state.entities[*parent_index];
-            entry.add_component(Parent(parent));
-            let mut parent_entry = world.entry(parent).unwrap();
-            if let Ok(children) = parent_entry.get_component_mut::<Children>() {
-                children.0.push(entity);
+                // Children of prefab instances always have a parent.
+                let entity_name = match &parent_name {
+                    Some(parent_name) if source_collection != collection_to_spawn_in => {
+                        format!("{}{}", parent_name, node.name)
+                    }
+                    _ => node.name.clone(),
+                };
+                let entity_index = app_state.entities.len();
+                app_state.entity_indices.insert(entity_name.clone(), entity_index);
+                app_state.entities.push(entity);
+
+                // Entities whose parent wasn't spawned become roots.
+                let parent_index = parent_name.as_ref()
+                    .and_then(|parent_name| app_state.entity_indices.get(parent_name).copied());
+                if let (Some(parent_name), None) = (&parent_name, parent_index) {
+                    app_state.scene_problems.push(dangling_reference("Parent", parent_name));
+                }
+                if let Some(parent_index) = parent_index {
+                    let parent = app_state.entities[parent_index];
+                    entry.add_component(Parent(parent));
+                    let mut parent_entry = world.entry(parent).unwrap();
+                    if let Ok(children) = parent_entry.get_component_mut::<Children>() {
+                        children.0.push(entity);
+                    }
+                    else {
+                        parent_entry.add_component(Children(smallvec![entity]));
+                    }
+                }
+                else {
+                    app_state.root_entities.push(entity);
+                }
+                Some((entity_name.clone(), children, source_node))
             }
-            else {
-                parent_entry.add_component(Children(smallvec![entity]));
+            Err(e) => {
+                scene_problems.push(e);
+                None
             }
         }
-        else {
-            app_state.root_entities.push(entity);
-        }
-        (entity_name.clone(), children)
+    };
+    let (entity_name, children, source_node) = match spawned {
+        Some(spawned) => spawned,
+        None => return,
     };
 
+    // A prefab that has itself as a child would be spawned forever.
+    context.ancestors.push((source_collection, index));
     for (child_index, collection) in &children {
-        spawn_entities_recursive(*child_index, *collection, collection_to_spawn_in, Some(entity_name.clone()), world, resources);
+        if context.ancestors.contains(&(*collection, *child_index)) {
+            let app_state = &mut *resources.get_mut::<AppState>().unwrap();
+            let child_collection = &app_state.data_accessor.collections[*collection];
+            let problem = DataError::CyclicPrefab {
+                collection: child_collection.name.clone(),
+                node: entity_name.clone(),
+                prefab: child_collection.scene_nodes[*child_index].name.clone(),
+            };
+            app_state.scene_problems.push(problem);
+            continue;
+        }
+        spawn_entities_recursive(*child_index, *collection, Some(entity_name.clone()), source_node, context, world, resources);
     }
+    context.ancestors.pop();
 }
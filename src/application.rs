@@ -1,28 +1,30 @@
 use std::time::Instant;
 use legion::*;
-use physics::TimeSinceLastPhysicsUpdate;
 use winit::{
     event::*,
     event_loop::{ControlFlow, EventLoop},
     window::{Window, WindowBuilder},
 };
 use rapier2d::{
-    dynamics::{JointSet, RigidBodySet, IntegrationParameters},
-    geometry::{BroadPhase, NarrowPhase, ColliderSet},
-    pipeline::PhysicsPipeline,
+    dynamics::{JointSet, RigidBodySet},
+    geometry::ColliderSet,
 };
 use crate::{
     wgpu_state::{self, WgpuState},
-    ui::UiState,
+    ui::{UiState, UiFocus},
     app_state::{self, AppState},
     transform,
-    events::{self, Events},
+    events::{self, Events, MouseEvent},
     command::Command,
     camera::{self, Camera},
+    gizmo::{self, Gizmo},
     asset::{self, Assets},
     mesh,
     texture::Texture,
     physics,
+    file_watcher::{self, FileWatcher},
+    project::Project,
+    static_data::PhysicsSettings,
 };
 
 pub struct DeltaTime(pub f32);
@@ -33,33 +35,41 @@ pub struct Application {
 }
 
 impl Application {
-    pub fn new() -> Self {
+    pub fn new(project: Project) -> Self {
         env_logger::init();
 
+        let mut resources = Resources::default();
+        resources.insert(project);
+
         Self {
             world: World::default(),
-            resources: Resources::default(),
+            resources,
         }
     }
 
     fn setup_world(&mut self, window: &Window) {
         self.resources.insert(futures::executor::block_on(WgpuState::new(window)));
-        self.resources.insert(AppState::new());
+        let app_state = AppState::new(&self.resources.get::<Project>().unwrap());
+        self.resources.insert(FileWatcher::new(&app_state.data_accessor.directory));
+        self.resources.insert(app_state);
         self.resources.insert(Events::<Command>::default());
         self.resources.insert(Events::<winit::event::KeyboardInput>::default());
-        self.resources.insert(Camera::new());
+        self.resources.insert(ModifiersState::default());
+        self.resources.insert(Events::<MouseEvent>::default());
+        self.resources.insert(UiFocus::default());
+        self.resources.insert(Camera::new(window.inner_size().into(), window.scale_factor() as f32));
+        self.resources.insert(Gizmo::new());
         self.resources.insert(Assets::<Texture>::new());
+        self.resources.insert(Assets::<mesh::MeshBuffers>::new());
         self.resources.insert(DeltaTime(0.0));
 
         {
-            self.resources.insert(PhysicsPipeline::new());
-            self.resources.insert(IntegrationParameters::default());
-            self.resources.insert(BroadPhase::new());
-            self.resources.insert(NarrowPhase::new());
+            self.resources.insert(physics::PhysicsStepper::new());
+            self.resources.insert(physics::PhysicsParameters::new(&PhysicsSettings::default()));
             self.resources.insert(RigidBodySet::new());
             self.resources.insert(ColliderSet::new());
             self.resources.insert(JointSet::new());
-            self.resources.insert(physics::TimeSinceLastPhysicsUpdate(0.0));
+            self.resources.insert(physics::Simulation::new());
         }
 
         let mesh_pipeline = {
@@ -78,7 +88,9 @@ impl Application {
     pub fn run(mut self) {
         let mut schedule_1 = Schedule::builder()
             .add_system(app_state::handle_input_system())
+            .add_system(file_watcher::watch_files_system())
             .add_system(camera::update_camera_system())
+            .add_system(gizmo::handle_gizmo_input_system())
             .add_system(physics::update_physics_system())
             .add_system(physics::copy_transforms_from_rigid_bodies_system())
             .build();
@@ -87,13 +99,24 @@ impl Application {
         let mut schedule_2 = Schedule::builder()
             .add_system(wgpu_state::prepare_frame_system())
             .add_system(mesh::render_meshes_system())
+            .add_system(gizmo::update_gizmo_target_system())
             .add_system(events::clear_events_system::<winit::event::KeyboardInput>())
+            .add_system(events::clear_events_system::<MouseEvent>())
+            .add_system(asset::remove_unused_assets_system::<mesh::MeshBuffers>())
             .add_system(asset::remove_unused_assets_system::<Texture>())
             .build();
 
         let event_loop = EventLoop::new();
+        let title = {
+            let project = self.resources.get::<Project>().unwrap();
+            if project.name.is_empty() {
+                "2D Editor".to_string()
+            } else {
+                format!("2D Editor - {}", project.name)
+            }
+        };
         let window = WindowBuilder::new()
-            .with_title("2D Editor")
+            .with_title(title)
             .with_maximized(true)
             .build(&event_loop)
             .unwrap();
@@ -119,6 +142,26 @@ impl Application {
                             let mut keyboard_events = self.resources.get_mut::<Events::<KeyboardInput>>().unwrap();
                             keyboard_events.send(input.clone());
                         }
+                        WindowEvent::MouseInput { button, state, .. } => {
+                            let mut mouse_events = self.resources.get_mut::<Events::<MouseEvent>>().unwrap();
+                            mouse_events.send(MouseEvent::Button { button: *button, state: *state });
+                        }
+                        WindowEvent::CursorMoved { position, .. } => {
+                            let mut mouse_events = self.resources.get_mut::<Events::<MouseEvent>>().unwrap();
+                            mouse_events.send(MouseEvent::Moved((position.x as f32, position.y as f32)));
+                        }
+                        WindowEvent::MouseWheel { delta, .. } => {
+                            let lines = match delta {
+                                MouseScrollDelta::LineDelta(_, y) => *y,
+                                MouseScrollDelta::PixelDelta(position) => position.y as f32 / 20.0,
+                            };
+                            let mut mouse_events = self.resources.get_mut::<Events::<MouseEvent>>().unwrap();
+                            mouse_events.send(MouseEvent::Wheel(lines));
+                        }
+                        WindowEvent::ModifiersChanged(new_modifiers) => {
+                            let mut modifiers = self.resources.get_mut::<ModifiersState>().unwrap();
+                            *modifiers = *new_modifiers;
+                        }
                         /*WindowEvent::KeyboardInput { input, .. } => match input {
                             KeyboardInput {
                                 state: ElementState::Pressed,
@@ -131,11 +174,13 @@ impl Application {
                             let mut wgpu_state = self.resources.get_mut::<WgpuState>().unwrap();
                             let mut camera = self.resources.get_mut::<Camera>().unwrap();
                             wgpu_state.resize(*physical_size);
-                            camera.size = wgpu_state.size.into();
+                            camera.resize(wgpu_state.size.into(), window.scale_factor() as f32);
                         }
-                        WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                        WindowEvent::ScaleFactorChanged { scale_factor, new_inner_size } => {
                             let mut wgpu_state = self.resources.get_mut::<WgpuState>().unwrap();
+                            let mut camera = self.resources.get_mut::<Camera>().unwrap();
                             wgpu_state.resize(**new_inner_size);
+                            camera.resize(wgpu_state.size.into(), *scale_factor as f32);
                         }
                         _ => {}
                     }
@@ -147,8 +192,8 @@ impl Application {
                         delta_time.0 = start.elapsed().as_secs_f32();
                         start = Instant::now();
 
-                        let mut time_since_last_physics_update = self.resources.get_mut::<TimeSinceLastPhysicsUpdate>().unwrap();
-                        time_since_last_physics_update.0 += delta_time.0;
+                        let mut simulation = self.resources.get_mut::<physics::Simulation>().unwrap();
+                        simulation.time_since_last_update += delta_time.0;
                     }
 
                     schedule_1.execute(&mut self.world, &mut self.resources);
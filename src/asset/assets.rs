@@ -34,6 +34,11 @@ impl<T> Assets<T> {
         self.assets.get_mut(&*handle.id).and_then(|x| Some(&mut x.0))
     }
 
+    // Removes all assets, including those that are still in use. Their handles won't resolve anymore.
+    pub fn clear(&mut self) {
+        self.assets.clear();
+    }
+
     pub fn get_handle(&self, id: u64) -> Option<Handle<T>> {
         if self.assets.contains_key(&id) {
             return Some(Handle::new(self.assets.get(&id).unwrap().1.clone()));
@@ -0,0 +1,407 @@
+use anyhow::{bail, Context, Result};
+use image::RgbaImage;
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    fs,
+    path::{Path, PathBuf},
+};
+use crate::static_data::{AtlasRegion, DataAccessor, TextureAtlas};
+
+// Transparent pixels between packed images, so that filtering doesn't bleed into neighbouring regions.
+const PADDING: u32 = 2;
+
+pub struct PackResult {
+    pub atlases: Vec<TextureAtlas>,
+
+    // Collections with meshes that now use the atlas pages.
+    pub changed_collections: Vec<usize>,
+
+    // Meshes that use a packed image but couldn't be changed, because their texture coordinates aren't within it.
+    pub skipped_meshes: Vec<String>,
+
+    // Pages of a previous version of the atlas that are no longer needed. Their files are deleted.
+    pub removed_atlases: Vec<String>,
+}
+
+// Part of a texture that has been moved to an atlas page. Rectangles are in pixels.
+struct Placement {
+    source_rect: (u32, u32, u32, u32),
+    source_size: (u32, u32),
+    page: usize,
+    page_rect: (u32, u32, u32, u32),
+}
+
+// Packs the PNG images in a directory into atlas pages, which are written to the data directory as `<name>.png` and
+// `<name>.json`, or with a page number if there is more than one. Meshes that use the packed images, or the regions of
+// a previous version of the atlas, are changed to use the pages. Changed collections are only updated in memory, so the
+// caller has to save them.
+pub fn pack_directory(
+    data_accessor: &mut DataAccessor,
+    image_directory: &Path,
+    name: &str,
+    max_size: u32,
+) -> Result<PackResult> {
+    // Pages of existing atlases aren't packed again if they are in the same directory.
+    let atlas_textures: Vec<PathBuf> = data_accessor.atlases.iter()
+        .map(|atlas| canonical_path(&data_accessor.get_texture_path(&atlas.texture)))
+        .collect();
+    let mut image_paths = Vec::new();
+    for entry in fs::read_dir(image_directory).with_context(|| format!("Failed to read {}", image_directory.display()))? {
+        let path = entry?.path();
+        if path.extension() == Some(OsStr::new("png")) && !atlas_textures.contains(&canonical_path(&path)) {
+            image_paths.push(path);
+        }
+    }
+    if image_paths.is_empty() {
+        bail!("{} contains no PNG images", image_directory.display());
+    }
+    image_paths.sort();
+
+    let mut images = Vec::new();
+    for path in &image_paths {
+        let image = image::open(path).with_context(|| format!("Failed to load {}", path.display()))?;
+        images.push(image.to_rgba8());
+    }
+    let sizes: Vec<(u32, u32)> = images.iter().map(|image| image.dimensions()).collect();
+    let (positions, page_sizes) = pack_rects(&sizes, max_size)?;
+
+    let page_names: Vec<String> = if page_sizes.len() == 1 {
+        vec![name.to_string()]
+    }
+    else {
+        (0..page_sizes.len()).map(|page| format!("{}_{}", name, page)).collect()
+    };
+
+    let mut pages: Vec<RgbaImage> = page_sizes.iter().map(|(width, height)| RgbaImage::new(*width, *height)).collect();
+    let mut page_regions: Vec<Vec<AtlasRegion>> = vec![Vec::new(); page_sizes.len()];
+    let mut placements: HashMap<PathBuf, Vec<Placement>> = HashMap::new();
+    for (i, (page, x, y)) in positions.iter().enumerate() {
+        let (width, height) = sizes[i];
+        image::imageops::replace(&mut pages[*page], &images[i], *x, *y);
+        page_regions[*page].push(AtlasRegion {
+            name: image_paths[i].file_name().unwrap().to_string_lossy().to_string(),
+            x: *x,
+            y: *y,
+            width,
+            height,
+            rotated: false,
+            offset: (0, 0),
+            source_size: (width, height),
+            pivot: (0.5, 0.5),
+        });
+        placements.entry(canonical_path(&image_paths[i])).or_default().push(Placement {
+            source_rect: (0, 0, width, height),
+            source_size: (width, height),
+            page: *page,
+            page_rect: (*x, *y, width, height),
+        });
+    }
+
+    // Meshes that use a previous version of the atlas are moved along with their regions.
+    let is_previous_page = |atlas: &TextureAtlas| {
+        atlas.name == name || atlas.name.strip_prefix(name)
+            .and_then(|suffix| suffix.strip_prefix('_'))
+            .is_some_and(|page| page.parse::<usize>().is_ok())
+    };
+    for old_atlas in data_accessor.atlases.iter().filter(|atlas| is_previous_page(atlas)) {
+        let old_texture_path = canonical_path(&data_accessor.get_texture_path(&old_atlas.texture));
+        for old_region in old_atlas.regions.iter().filter(|region| !region.rotated) {
+            let new_region = page_regions.iter().enumerate().find_map(|(page, regions)| {
+                regions.iter().find(|region| region.name == old_region.name).map(|region| (page, region))
+            });
+            if let Some((page, region)) = new_region {
+                placements.entry(old_texture_path.clone()).or_default().push(Placement {
+                    source_rect: (old_region.x, old_region.y, old_region.width, old_region.height),
+                    source_size: (old_atlas.width, old_atlas.height),
+                    page,
+                    page_rect: (region.x, region.y, region.width, region.height),
+                });
+            }
+        }
+    }
+
+    let mut atlases = Vec::new();
+    for (page, page_name) in page_names.iter().enumerate() {
+        let image_name = format!("{}.png", page_name);
+        let image_path = data_accessor.get_texture_path(&image_name);
+        pages[page].save(&image_path).with_context(|| format!("Failed to write {}", image_path.display()))?;
+        let (width, height) = page_sizes[page];
+        let atlas = TextureAtlas::new(page_name.clone(), image_name, width, height, page_regions[page].clone());
+        atlas.save(&image_path.with_extension("json"))?;
+        atlases.push(atlas);
+    }
+
+    let (changed_collections, skipped_meshes) = remap_meshes(data_accessor, &placements, &atlases);
+    for atlas in &atlases {
+        let path = data_accessor.get_texture_path(&atlas.name).with_extension("json");
+        data_accessor.reload_atlas(&path)?;
+    }
+
+    // Previous pages would otherwise be loaded again, e.g. when packing into fewer pages. They are only removed after
+    // remapping, since their textures are matched by their canonical path.
+    let stale_atlases: Vec<(String, PathBuf)> = data_accessor.atlases.iter()
+        .filter(|atlas| is_previous_page(atlas) && !page_names.contains(&atlas.name))
+        .map(|atlas| (atlas.name.clone(), data_accessor.get_texture_path(&atlas.texture)))
+        .collect();
+    let mut removed_atlases = Vec::new();
+    for (atlas_name, texture_path) in stale_atlases {
+        let path = data_accessor.get_texture_path(&atlas_name).with_extension("json");
+        for path in [path, texture_path].iter().filter(|path| path.is_file()) {
+            fs::remove_file(path).with_context(|| format!("Failed to remove {}", path.display()))?;
+        }
+        data_accessor.unload_atlas(&atlas_name);
+        removed_atlases.push(atlas_name);
+    }
+    Ok(PackResult { atlases, changed_collections, skipped_meshes, removed_atlases })
+}
+
+// The page and position of each rectangle, and the size of each page.
+type PackedRects = (Vec<(usize, u32, u32)>, Vec<(u32, u32)>);
+
+// Places the rectangles on shelves, tallest first, and starts a new page when one is full. Returns the page and position
+// of each rectangle, and the size of each page rounded up to a power of two.
+fn pack_rects(sizes: &[(u32, u32)], max_size: u32) -> Result<PackedRects> {
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by_key(|i| std::cmp::Reverse(sizes[*i].1));
+
+    let mut positions = vec![(0, 0, 0); sizes.len()];
+    let mut page_sizes = vec![(0, 0)];
+    // The top, height and used width of the shelves on the current page.
+    let mut shelves: Vec<(u32, u32, u32)> = Vec::new();
+    for i in order {
+        let (width, height) = sizes[i];
+        if width > max_size || height > max_size {
+            bail!("An image of {}x{} pixels doesn't fit on a page of {} pixels", width, height, max_size);
+        }
+
+        let shelf_index = shelves.iter().position(|(_, shelf_height, used_width)| {
+            height <= *shelf_height && used_width + width <= max_size
+        });
+        let shelf_index = match shelf_index {
+            Some(index) => index,
+            None => {
+                let top = shelves.last().map_or(0, |(top, shelf_height, _)| top + shelf_height + PADDING);
+                if top + height > max_size {
+                    shelves.clear();
+                    page_sizes.push((0, 0));
+                    shelves.push((0, height, 0));
+                }
+                else {
+                    shelves.push((top, height, 0));
+                }
+                shelves.len() - 1
+            }
+        };
+
+        let (top, _, used_width) = &mut shelves[shelf_index];
+        let page = page_sizes.len() - 1;
+        positions[i] = (page, *used_width, *top);
+        let page_size = &mut page_sizes[page];
+        page_size.0 = page_size.0.max(*used_width + width);
+        page_size.1 = page_size.1.max(*top + height);
+        *used_width += width + PADDING;
+    }
+
+    let page_sizes = page_sizes.into_iter()
+        .map(|(width, height)| (width.next_power_of_two().min(max_size), height.next_power_of_two().min(max_size)))
+        .collect();
+    Ok((positions, page_sizes))
+}
+
+fn remap_meshes(
+    data_accessor: &mut DataAccessor,
+    placements: &HashMap<PathBuf, Vec<Placement>>,
+    atlases: &[TextureAtlas],
+) -> (Vec<usize>, Vec<String>) {
+    let texture_paths: HashMap<String, PathBuf> = data_accessor.collections.iter()
+        .flat_map(|collection| collection.meshes.iter())
+        .map(|mesh| (mesh.texture.clone(), canonical_path(&data_accessor.get_texture_path(&mesh.texture))))
+        .collect();
+
+    let mut changed_collections = Vec::new();
+    let mut skipped_meshes = Vec::new();
+    for (index, collection) in data_accessor.collections.iter_mut().enumerate() {
+        let mut has_changed = false;
+        for mesh in collection.meshes.iter_mut().filter(|mesh| !mesh.is_generated) {
+            let texture_placements = match placements.get(&texture_paths[&mesh.texture]) {
+                Some(texture_placements) => texture_placements,
+                None => continue,
+            };
+
+            // The placement that contains all texture coordinates of the mesh. Texture coordinates start at the bottom,
+            // while rectangles start at the top.
+            let contains = |placement: &&Placement| {
+                let (x, y, width, height) = placement.source_rect;
+                mesh.vertices.iter().all(|vertex| {
+                    let u = vertex.u * placement.source_size.0 as f32;
+                    let v = (1.0 - vertex.v) * placement.source_size.1 as f32;
+                    u >= x as f32 - 0.5 && u <= (x + width) as f32 + 0.5 && v >= y as f32 - 0.5 && v <= (y + height) as f32 + 0.5
+                })
+            };
+            let placement = match texture_placements.iter().find(contains) {
+                Some(placement) => placement,
+                None => {
+                    skipped_meshes.push(format!("{}/{}", collection.name, mesh.mesh_name));
+                    continue;
+                }
+            };
+
+            let atlas = &atlases[placement.page];
+            for vertex in &mut mesh.vertices {
+                let u = vertex.u * placement.source_size.0 as f32 - placement.source_rect.0 as f32;
+                let v = (1.0 - vertex.v) * placement.source_size.1 as f32 - placement.source_rect.1 as f32;
+                vertex.u = (u + placement.page_rect.0 as f32) / atlas.width as f32;
+                vertex.v = 1.0 - (v + placement.page_rect.1 as f32) / atlas.height as f32;
+            }
+            mesh.texture = atlas.texture.clone();
+            has_changed = true;
+        }
+        if has_changed {
+            changed_collections.push(index);
+        }
+    }
+    (changed_collections, skipped_meshes)
+}
+
+// Textures are matched by their canonical path, since they may be referenced in different ways. Paths of files that
+// don't exist are kept as they are.
+fn canonical_path(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+    use crate::static_data::TestDirectory;
+
+    // A data directory with an empty directory for the images to pack.
+    fn test_directory(name: &str) -> TestDirectory {
+        let directory = TestDirectory::new(name);
+        fs::create_dir_all(directory.path.join("images")).unwrap();
+        directory
+    }
+
+    fn write_image(path: &Path, width: u32, height: u32, color: [u8; 4]) {
+        RgbaImage::from_pixel(width, height, Rgba(color)).save(path).unwrap();
+    }
+
+    // A quad that covers the whole texture.
+    fn write_collection(path: &Path, mesh_name: &str, texture: &str) {
+        let collection = format!(r#"(
+            meshes: [(
+                mesh_name: "{}",
+                texture: "{}",
+                vertices: [(x: 0.0, y: 0.0, u: 0.0, v: 0.0), (x: 1.0, y: 0.0, u: 1.0, v: 0.0),
+                    (x: 1.0, y: 1.0, u: 1.0, v: 1.0), (x: 0.0, y: 1.0, u: 0.0, v: 1.0)],
+                quads: [(v1: 0, v2: 1, v3: 2, v4: 3)],
+            )],
+        )"#, mesh_name, texture);
+        fs::write(path, collection).unwrap();
+    }
+
+    fn texture_coordinates(data_accessor: &DataAccessor, mesh_name: &str) -> Vec<(f32, f32)> {
+        data_accessor.collections.iter()
+            .flat_map(|collection| collection.meshes.iter())
+            .find(|mesh| mesh.mesh_name == mesh_name)
+            .unwrap()
+            .vertices.iter()
+            .map(|vertex| (vertex.u, vertex.v))
+            .collect()
+    }
+
+    #[test]
+    fn pack_rects_on_shelves() {
+        let (positions, page_sizes) = pack_rects(&[(8, 8), (8, 16), (20, 4)], 64).unwrap();
+        // Tallest first, with padding between the rectangles.
+        assert_eq!(positions, vec![(0, 10, 0), (0, 0, 0), (0, 20, 0)]);
+        assert_eq!(page_sizes, vec![(64, 16)]);
+    }
+
+    #[test]
+    fn pack_rects_on_several_pages() {
+        let (positions, page_sizes) = pack_rects(&[(16, 16), (16, 16), (16, 16)], 32).unwrap();
+        assert_eq!(positions, vec![(0, 0, 0), (1, 0, 0), (2, 0, 0)]);
+        assert_eq!(page_sizes, vec![(16, 16), (16, 16), (16, 16)]);
+    }
+
+    #[test]
+    fn pack_rects_too_large() {
+        assert!(pack_rects(&[(8, 8), (40, 8)], 32).is_err());
+    }
+
+    #[test]
+    fn pack_remaps_meshes() {
+        let directory = test_directory("pack");
+        write_image(&directory.path.join("images").join("small.png"), 8, 8, [255, 0, 0, 255]);
+        write_image(&directory.path.join("images").join("tall.png"), 8, 16, [0, 0, 255, 255]);
+        write_collection(&directory.path.join("scene.ron"), "small", "images/small.png");
+        let mut data_accessor = DataAccessor::new();
+        data_accessor.load_collections_in_directory(&directory.path);
+
+        let result = pack_directory(&mut data_accessor, &directory.path.join("images"), "page", 64).unwrap();
+        assert_eq!(result.changed_collections, vec![0]);
+        assert!(result.skipped_meshes.is_empty());
+        assert_eq!(result.atlases.len(), 1);
+        assert_eq!((result.atlases[0].width, result.atlases[0].height), (32, 16));
+
+        // The small image is placed right of the tall one, in the bottom half of the page.
+        assert_eq!(
+            texture_coordinates(&data_accessor, "small"),
+            vec![(10.0 / 32.0, 0.5), (18.0 / 32.0, 0.5), (18.0 / 32.0, 1.0), (10.0 / 32.0, 1.0)],
+        );
+        let page = image::open(directory.path.join("page.png")).unwrap().to_rgba8();
+        assert_eq!(*page.get_pixel(10, 0), Rgba([255, 0, 0, 255]));
+        assert_eq!(*page.get_pixel(10, 8), Rgba([0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn repack_moves_atlas_regions() {
+        let directory = test_directory("repack");
+        write_image(&directory.path.join("images").join("small.png"), 8, 8, [255, 0, 0, 255]);
+        write_image(&directory.path.join("images").join("tall.png"), 8, 16, [0, 0, 255, 255]);
+        write_collection(&directory.path.join("scene.ron"), "small", "images/small.png");
+        let mut data_accessor = DataAccessor::new();
+        data_accessor.load_collections_in_directory(&directory.path);
+        pack_directory(&mut data_accessor, &directory.path.join("images"), "page", 64).unwrap();
+
+        // A taller image moves the others to the right.
+        write_image(&directory.path.join("images").join("taller.png"), 4, 32, [0, 255, 0, 255]);
+        let result = pack_directory(&mut data_accessor, &directory.path.join("images"), "page", 64).unwrap();
+        assert!(result.skipped_meshes.is_empty());
+        assert_eq!((result.atlases[0].width, result.atlases[0].height), (32, 32));
+        assert_eq!(
+            texture_coordinates(&data_accessor, "small"),
+            vec![(16.0 / 32.0, 0.75), (24.0 / 32.0, 0.75), (24.0 / 32.0, 1.0), (16.0 / 32.0, 1.0)],
+        );
+    }
+
+    #[test]
+    fn repack_into_fewer_pages_removes_stale_pages() {
+        let directory = test_directory("repack-pages");
+        write_image(&directory.path.join("images").join("small.png"), 8, 8, [255, 0, 0, 255]);
+        write_image(&directory.path.join("images").join("tall.png"), 8, 16, [0, 0, 255, 255]);
+        write_collection(&directory.path.join("scene.ron"), "small", "images/small.png");
+        let mut data_accessor = DataAccessor::new();
+        data_accessor.load_collections_in_directory(&directory.path);
+        let result = pack_directory(&mut data_accessor, &directory.path.join("images"), "page", 16).unwrap();
+        assert_eq!(result.atlases.len(), 2);
+
+        fs::remove_file(directory.path.join("images").join("tall.png")).unwrap();
+        let result = pack_directory(&mut data_accessor, &directory.path.join("images"), "page", 16).unwrap();
+        assert_eq!(result.removed_atlases, vec!["page_0", "page_1"]);
+        let atlas_names: Vec<&str> = data_accessor.atlases.iter().map(|atlas| atlas.name.as_str()).collect();
+        assert_eq!(atlas_names, vec!["page"]);
+        assert_eq!(data_accessor.atlas_indices["page"], 0);
+        for file_name in &["page_0.json", "page_0.png", "page_1.json", "page_1.png"] {
+            assert!(!directory.path.join(file_name).exists());
+        }
+
+        // The mesh moves from the second page of the previous version to the new one.
+        assert_eq!(
+            texture_coordinates(&data_accessor, "small"),
+            vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)],
+        );
+    }
+}
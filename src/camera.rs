@@ -1,13 +1,21 @@
 use legion::*;
-use winit::event::{ElementState, KeyboardInput, VirtualKeyCode};
+use winit::event::{ElementState, KeyboardInput, ModifiersState, MouseButton, VirtualKeyCode};
 use crate::{
     application::DeltaTime,
-    events::Events,
+    events::{Events, MouseEvent},
+    ui::UiFocus,
 };
 
+const MIN_ZOOM: f32 = 0.05;
+const MAX_ZOOM: f32 = 20.0;
+const ZOOM_STEP: f32 = 1.1;
+
 pub struct Camera {
     eye: glam::Vec3,
+    // Window size in physical pixels.
     pub size: (f32, f32),
+    pub scale_factor: f32,
+    pub zoom: f32,
     znear: f32,
     zfar: f32,
 
@@ -15,13 +23,18 @@ pub struct Camera {
     is_down_pressed: bool,
     is_left_pressed: bool,
     is_right_pressed: bool,
+
+    is_panning: bool,
+    cursor_position: (f32, f32),
 }
 
 impl Camera {
-    pub fn new() -> Self {
+    pub fn new(size: (f32, f32), scale_factor: f32) -> Self {
         Self {
             eye: (0.0, 0.0, 20.0).into(),
-            size: (800.0, 600.0),
+            size,
+            scale_factor,
+            zoom: 1.0,
             znear: 0.1,
             zfar: 100.0,
 
@@ -29,14 +42,72 @@ impl Camera {
             is_down_pressed: false,
             is_left_pressed: false,
             is_right_pressed: false,
+
+            is_panning: false,
+            cursor_position: (0.0, 0.0),
         }
     }
 
     pub fn build_view_projection_matrix(&self) -> glam::Mat4 {
         let view = glam::Mat4::look_at_rh(self.eye, glam::Vec3::new(self.eye.x, self.eye.y, 0.0), glam::Vec3::unit_y());
-        let proj = glam::Mat4::orthographic_rh(0.0, self.size.0, 0.0, self.size.1, self.znear, self.zfar);
+        let proj = glam::Mat4::orthographic_rh(0.0, self.size.0 / self.zoom, 0.0, self.size.1 / self.zoom, self.znear, self.zfar);
         proj * view
     }
+
+    // Screen positions are in physical window coordinates with the origin in the top left corner, like winit's cursor
+    // positions. Logical positions are divided by the scale factor, like imgui's.
+    pub fn screen_to_world(&self, position: (f32, f32)) -> glam::Vec2 {
+        glam::Vec2::new(
+            self.eye.x + position.0 / self.zoom,
+            self.eye.y + (self.size.1 - position.1) / self.zoom,
+        )
+    }
+
+    pub fn world_to_screen(&self, position: glam::Vec2) -> (f32, f32) {
+        (
+            (position.x - self.eye.x) * self.zoom,
+            self.size.1 - (position.y - self.eye.y) * self.zoom,
+        )
+    }
+
+    pub fn logical_to_world(&self, position: (f32, f32)) -> glam::Vec2 {
+        self.screen_to_world((position.0 * self.scale_factor, position.1 * self.scale_factor))
+    }
+
+    pub fn world_to_logical(&self, position: glam::Vec2) -> (f32, f32) {
+        let position = self.world_to_screen(position);
+        (position.0 / self.scale_factor, position.1 / self.scale_factor)
+    }
+
+    pub fn cursor_world_position(&self) -> glam::Vec2 {
+        self.screen_to_world(self.cursor_position)
+    }
+
+    pub fn resize(&mut self, size: (f32, f32), scale_factor: f32) {
+        self.size = size;
+        self.scale_factor = scale_factor;
+    }
+
+    // Zooms while keeping the world position under the given screen position in place.
+    pub fn zoom_at(&mut self, zoom: f32, position: (f32, f32)) {
+        let world_position = self.screen_to_world(position);
+        self.zoom = zoom.clamp(MIN_ZOOM, MAX_ZOOM);
+        self.eye.x = world_position.x - position.0 / self.zoom;
+        self.eye.y = world_position.y - (self.size.1 - position.1) / self.zoom;
+    }
+
+    // Centers the camera on the given world space bounds and zooms so that they fill most of the window.
+    pub fn frame(&mut self, min: glam::Vec2, max: glam::Vec2) {
+        let margin = 1.2;
+        let extent = (max - min) * margin;
+        let zoom_x = if extent.x > 0.0 { self.size.0 / extent.x } else { MAX_ZOOM };
+        let zoom_y = if extent.y > 0.0 { self.size.1 / extent.y } else { MAX_ZOOM };
+        self.zoom = zoom_x.min(zoom_y).clamp(MIN_ZOOM, MAX_ZOOM);
+
+        let center = (min + max) * 0.5;
+        self.eye.x = center.x - self.size.0 * 0.5 / self.zoom;
+        self.eye.y = center.y - self.size.1 * 0.5 / self.zoom;
+    }
 }
 
 #[system]
@@ -44,6 +115,9 @@ pub fn update_camera(
     #[resource] camera: &mut Camera,
     #[resource] delta_time: &DeltaTime,
     #[resource] input_events: &mut Events::<KeyboardInput>,
+    #[resource] mouse_events: &Events::<MouseEvent>,
+    #[resource] ui_focus: &UiFocus,
+    #[resource] modifiers: &ModifiersState,
 ) {
     for event in &input_events.events {
         match event {
@@ -52,7 +126,9 @@ pub fn update_camera(
                 virtual_keycode: Some(keycode),
                 ..
             } => {
-                let is_pressed = *state == ElementState::Pressed;
+                // Movement keys are also part of shortcuts like Ctrl+S.
+                let is_pressed = *state == ElementState::Pressed && !ui_focus.wants_keyboard
+                    && !modifiers.ctrl() && !modifiers.alt();
                 match keycode {
                     VirtualKeyCode::W => {
                         camera.is_up_pressed = is_pressed;
@@ -73,6 +149,29 @@ pub fn update_camera(
         }
     }
 
+    for event in &mouse_events.events {
+        match event {
+            MouseEvent::Button { button: MouseButton::Middle, state } => {
+                camera.is_panning = *state == ElementState::Pressed && !ui_focus.wants_mouse;
+            }
+            MouseEvent::Moved(position) => {
+                if camera.is_panning {
+                    camera.eye.x -= (position.0 - camera.cursor_position.0) / camera.zoom;
+                    camera.eye.y += (position.1 - camera.cursor_position.1) / camera.zoom;
+                }
+                camera.cursor_position = *position;
+            }
+            MouseEvent::Wheel(lines) => {
+                if !ui_focus.wants_mouse {
+                    let zoom = camera.zoom * ZOOM_STEP.powf(*lines);
+                    let cursor_position = camera.cursor_position;
+                    camera.zoom_at(zoom, cursor_position);
+                }
+            }
+            _ => {}
+        }
+    }
+
     let mut direction = glam::Vec3::zero();
     if camera.is_up_pressed {
         direction += glam::Vec3::unit_y();
@@ -87,7 +186,7 @@ pub fn update_camera(
         direction += glam::Vec3::unit_x();
     }
     if direction != glam::Vec3::zero() {
-        direction = direction.normalize() * 500.0 * delta_time.0;
+        direction = direction.normalize() * 500.0 / camera.zoom * delta_time.0;
     }
 
     camera.eye += direction;
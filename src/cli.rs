@@ -0,0 +1,175 @@
+use anyhow::{anyhow, bail, Context, Result};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use crate::{
+    atlas_packer,
+    project::Project,
+    static_data::DataAccessor,
+};
+
+const USAGE: &str = "\
+Usage:
+    nox-2d-editor [project]
+    nox-2d-editor validate [project]
+    nox-2d-editor export [--flatten] <output directory> [project]
+    nox-2d-editor pack [--max-size <pixels>] <image directory> <atlas name> [project]";
+
+const DEFAULT_MAX_ATLAS_SIZE: u32 = 2048;
+
+// Commands that work on the project data without opening a window, e.g. in a build pipeline.
+pub enum CliCommand {
+    Validate { project_path: PathBuf },
+    Export { project_path: PathBuf, output_directory: PathBuf, flatten: bool },
+    Pack { project_path: PathBuf, image_directory: PathBuf, atlas_name: String, max_size: u32 },
+}
+
+impl CliCommand {
+    // Returns `None` if the arguments don't start with a command, which means that the editor should be opened.
+    pub fn from_args(args: &[String]) -> Option<Result<Self>> {
+        let (command, args) = args.split_first()?;
+        let result = match command.as_str() {
+            "validate" => Self::parse_validate(args),
+            "export" => Self::parse_export(args),
+            "pack" => Self::parse_pack(args),
+            _ => return None,
+        };
+        Some(result.map_err(|e| anyhow!("{}\n\n{}", e, USAGE)))
+    }
+
+    fn parse_validate(args: &[String]) -> Result<Self> {
+        if args.len() > 1 {
+            bail!("Too many arguments");
+        }
+        Ok(CliCommand::Validate { project_path: get_project_path(args.first()) })
+    }
+
+    fn parse_export(args: &[String]) -> Result<Self> {
+        let flatten = args.first().is_some_and(|arg| arg == "--flatten");
+        let args = if flatten { &args[1..] } else { args };
+        let output_directory = args.first().context("Missing output directory")?;
+        if args.len() > 2 {
+            bail!("Too many arguments");
+        }
+        Ok(CliCommand::Export {
+            project_path: get_project_path(args.get(1)),
+            output_directory: PathBuf::from(output_directory),
+            flatten,
+        })
+    }
+
+    fn parse_pack(args: &[String]) -> Result<Self> {
+        let (max_size, args) = if args.first().is_some_and(|arg| arg == "--max-size") {
+            let max_size = args.get(1).context("Missing maximum atlas size")?;
+            let max_size = max_size.parse().with_context(|| format!("Invalid maximum atlas size {}", max_size))?;
+            (max_size, &args[2..])
+        }
+        else {
+            (DEFAULT_MAX_ATLAS_SIZE, args)
+        };
+        let image_directory = args.first().context("Missing image directory")?;
+        let atlas_name = args.get(1).context("Missing atlas name")?;
+        if args.len() > 3 {
+            bail!("Too many arguments");
+        }
+        Ok(CliCommand::Pack {
+            project_path: get_project_path(args.get(2)),
+            image_directory: PathBuf::from(image_directory),
+            atlas_name: atlas_name.clone(),
+            max_size,
+        })
+    }
+
+    // Prints the problems that were found. Returns the exit code.
+    pub fn run(&self) -> i32 {
+        let result = match self {
+            CliCommand::Validate { project_path } => {
+                load_and_validate(project_path).map(|(data_accessor, num_problems)| {
+                    println!("Checked {} collections, found {} problems", data_accessor.collections.len(), num_problems);
+                    num_problems == 0
+                })
+            }
+            CliCommand::Export { project_path, output_directory, flatten } => {
+                load_and_validate(project_path).and_then(|(data_accessor, num_problems)| {
+                    if num_problems > 0 {
+                        println!("Found {} problems, nothing exported", num_problems);
+                        return Ok(false);
+                    }
+                    export(&data_accessor, output_directory, *flatten)?;
+                    Ok(true)
+                })
+            }
+            CliCommand::Pack { project_path, image_directory, atlas_name, max_size } => {
+                load_and_validate(project_path).and_then(|(mut data_accessor, num_problems)| {
+                    if num_problems > 0 {
+                        println!("Found {} problems, nothing packed", num_problems);
+                        return Ok(false);
+                    }
+                    pack(&mut data_accessor, image_directory, atlas_name, *max_size)?;
+                    Ok(true)
+                })
+            }
+        };
+        match result {
+            Ok(true) => 0,
+            Ok(false) => 1,
+            Err(e) => {
+                eprintln!("Error: {:#}", e);
+                1
+            }
+        }
+    }
+}
+
+fn get_project_path(arg: Option<&String>) -> PathBuf {
+    PathBuf::from(arg.map_or(".", |arg| arg.as_str()))
+}
+
+// Returns the data and the number of problems found while loading and validating it.
+fn load_and_validate(project_path: &Path) -> Result<(DataAccessor, usize)> {
+    let project = Project::open(project_path)?;
+    let mut data_accessor = DataAccessor::new();
+    data_accessor.load_collections_in_directory(&project.data_directory);
+    let report = data_accessor.validate();
+    for problem in data_accessor.problems.iter().chain(report.problems.iter()) {
+        println!("{}", problem);
+    }
+    let num_problems = data_accessor.problems.len() + report.problems.len();
+    Ok((data_accessor, num_problems))
+}
+
+fn export(data_accessor: &DataAccessor, output_directory: &Path, flatten: bool) -> Result<()> {
+    fs::create_dir_all(output_directory)
+        .with_context(|| format!("Failed to create {}", output_directory.display()))?;
+    for (index, collection) in data_accessor.collections.iter().enumerate() {
+        let path = output_directory.join(&collection.name).with_extension("ron");
+        if flatten {
+            let flattened_collection = data_accessor.flatten_collection(index)?;
+            DataAccessor::write_collection(&flattened_collection, &path)?;
+        }
+        else {
+            data_accessor.save_collection_as(index, &path)?;
+        }
+        println!("Exported {}", path.display());
+    }
+    Ok(())
+}
+
+fn pack(data_accessor: &mut DataAccessor, image_directory: &Path, atlas_name: &str, max_size: u32) -> Result<()> {
+    let result = atlas_packer::pack_directory(data_accessor, image_directory, atlas_name, max_size)?;
+    for atlas in &result.atlases {
+        println!("Packed {} images into {} ({}x{})", atlas.regions.len(), atlas.texture, atlas.width, atlas.height);
+    }
+    for mesh in &result.skipped_meshes {
+        println!("Skipped {}, its texture coordinates are outside of the packed image", mesh);
+    }
+    for atlas in &result.removed_atlases {
+        println!("Removed {}, it is no longer needed", atlas);
+    }
+    for index in result.changed_collections {
+        data_accessor.save_collection(index)?;
+        println!("Updated {}", data_accessor.collections[index].path.display());
+    }
+    Ok(())
+}
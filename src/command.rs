@@ -1,4 +1,34 @@
+use std::path::PathBuf;
+use crate::history::Transaction;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SelectionMode {
+    Replace,
+    Add,
+    Toggle,
+}
+
 #[derive(Clone)]
 pub enum Command {
     SetCurrentScene(usize),
+    SaveCollection(usize),
+    Execute(Transaction),
+    Undo,
+    Redo,
+    Select(Vec<(usize, usize)>, SelectionMode),
+    // Selects the mesh under a world position.
+    Pick(glam::Vec2, SelectionMode),
+    FrameSelection,
+    FrameAll,
+    // Files in the data directory that were added or modified, and those that were removed.
+    ReloadFiles { changed: Vec<PathBuf>, removed: Vec<PathBuf> },
+    ValidateCollections,
+    // Render layers are identified by their index in the current collection.
+    SetLayerHidden(usize, bool),
+    SetLayerLocked(usize, bool),
+    // Physics only runs in play mode. Resetting restores the state from when play mode was entered.
+    PlaySimulation,
+    PauseSimulation,
+    StepSimulation,
+    ResetSimulation,
 }
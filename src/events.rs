@@ -1,4 +1,5 @@
 use legion::*;
+use winit::event::{ElementState, MouseButton};
 
 pub struct Events<T> {
     pub events: Vec<T>,
@@ -12,6 +13,15 @@ impl<T> Default for Events<T> {
     }
 }
 
+#[derive(Clone, Copy, Debug)]
+pub enum MouseEvent {
+    Button { button: MouseButton, state: ElementState },
+    // Cursor position in physical window coordinates.
+    Moved((f32, f32)),
+    // Scroll amount in lines, positive when scrolling up.
+    Wheel(f32),
+}
+
 impl<T> Events<T> {
     pub fn send(&mut self, event: T) {
         self.events.push(event);
@@ -0,0 +1,116 @@
+use legion::*;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+use crate::{
+    application::DeltaTime,
+    events::Events,
+    command::Command,
+};
+
+// Polls the modification times of the files in a directory, which is good enough for a handful of data files.
+pub struct FileWatcher {
+    directory: PathBuf,
+    modified_times: HashMap<PathBuf, SystemTime>,
+    interval: f32,
+    time_since_last_check: f32,
+}
+
+impl FileWatcher {
+    pub fn new(directory: &Path) -> Self {
+        let mut file_watcher = Self {
+            directory: directory.to_path_buf(),
+            modified_times: HashMap::new(),
+            interval: 0.5,
+            time_since_last_check: 0.0,
+        };
+        file_watcher.get_changed_files();
+        file_watcher
+    }
+
+    // Makes the watcher ignore changes that happened up to now, e.g. when the editor saved the file itself.
+    pub fn mark_unchanged(&mut self, path: &Path) {
+        if let Ok(modified_time) = fs::metadata(path).and_then(|metadata| metadata.modified()) {
+            self.modified_times.insert(path.to_path_buf(), modified_time);
+        }
+    }
+
+    // Returns the files that were added or modified since the last check, and those that were removed.
+    fn get_changed_files(&mut self) -> (Vec<PathBuf>, Vec<PathBuf>) {
+        let mut changed_files = Vec::new();
+        let entries = match fs::read_dir(&self.directory) {
+            Ok(entries) => entries,
+            Err(_) => return (changed_files, Vec::new()),
+        };
+        let mut existing_files = HashSet::new();
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let modified_time = match entry.metadata().and_then(|metadata| metadata.modified()) {
+                Ok(modified_time) => modified_time,
+                Err(_) => continue,
+            };
+            if !path.is_file() {
+                continue;
+            }
+            existing_files.insert(path.clone());
+            match self.modified_times.insert(path.clone(), modified_time) {
+                Some(previous_time) if previous_time == modified_time => {}
+                _ => changed_files.push(path),
+            }
+        }
+        changed_files.sort();
+
+        let mut removed_files: Vec<PathBuf> = self.modified_times.keys()
+            .filter(|path| !existing_files.contains(*path))
+            .cloned()
+            .collect();
+        for path in &removed_files {
+            self.modified_times.remove(path);
+        }
+        removed_files.sort();
+        (changed_files, removed_files)
+    }
+}
+
+#[system]
+pub fn watch_files(
+    #[resource] file_watcher: &mut FileWatcher,
+    #[resource] delta_time: &DeltaTime,
+    #[resource] commands: &mut Events::<Command>,
+) {
+    file_watcher.time_since_last_check += delta_time.0;
+    if file_watcher.time_since_last_check < file_watcher.interval {
+        return;
+    }
+    file_watcher.time_since_last_check = 0.0;
+
+    let (changed, removed) = file_watcher.get_changed_files();
+    if !changed.is_empty() || !removed.is_empty() {
+        commands.send(Command::ReloadFiles { changed, removed });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::static_data::TestDirectory;
+
+    #[test]
+    fn report_added_and_removed_files() {
+        let directory = TestDirectory::new("file-watcher");
+        fs::write(directory.path.join("a.ron"), "()").unwrap();
+        fs::write(directory.path.join("b.ron"), "()").unwrap();
+        let mut file_watcher = FileWatcher::new(&directory.path);
+        assert_eq!(file_watcher.get_changed_files(), (Vec::new(), Vec::new()));
+
+        fs::remove_file(directory.path.join("a.ron")).unwrap();
+        fs::write(directory.path.join("c.ron"), "()").unwrap();
+        assert_eq!(file_watcher.get_changed_files(), (vec![directory.path.join("c.ron")], vec![directory.path.join("a.ron")]));
+
+        // Removed files are only reported once.
+        assert_eq!(file_watcher.get_changed_files(), (Vec::new(), Vec::new()));
+    }
+}
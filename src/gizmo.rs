@@ -0,0 +1,140 @@
+use legion::*;
+use legion::world::SubWorld;
+use winit::event::{ElementState, KeyboardInput, ModifiersState, VirtualKeyCode};
+use crate::{
+    app_state::AppState,
+    events::Events,
+    hierarchy::Parent,
+    mesh,
+    transform::{Transform2D, GlobalTransform},
+    ui::UiFocus,
+};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum GizmoMode {
+    Translate,
+    Rotate,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum GizmoHandle {
+    AxisX,
+    AxisY,
+    Center,
+    Ring,
+}
+
+#[derive(Clone)]
+pub struct GizmoTarget {
+    pub node: (usize, usize),
+    pub global_transform: Transform2D,
+    pub parent_transform: Option<Transform2D>,
+}
+
+pub struct GizmoDrag {
+    pub handle: GizmoHandle,
+    pub target: GizmoTarget,
+    pub start_position: glam::Vec2,
+    pub has_edited: bool,
+}
+
+pub struct Gizmo {
+    pub mode: GizmoMode,
+    pub target: Option<GizmoTarget>,
+    pub drag: Option<GizmoDrag>,
+}
+
+impl Gizmo {
+    pub fn new() -> Self {
+        Self {
+            mode: GizmoMode::Translate,
+            target: None,
+            drag: None,
+        }
+    }
+
+    // Returns the new local transform of the dragged node for the given world position of the mouse.
+    pub fn drag_to(&self, position: glam::Vec2) -> Option<Transform2D> {
+        let drag = self.drag.as_ref()?;
+        let mut global_transform = drag.target.global_transform.clone();
+        let delta = position - drag.start_position;
+        match drag.handle {
+            GizmoHandle::AxisX => global_transform.translation.x += delta.x,
+            GizmoHandle::AxisY => global_transform.translation.y += delta.y,
+            GizmoHandle::Center => global_transform.translation += delta.extend(0.0),
+            GizmoHandle::Ring => {
+                let center = global_transform.translation.truncate();
+                let start_direction = drag.start_position - center;
+                let direction = position - center;
+                let angle = direction.y.atan2(direction.x) - start_direction.y.atan2(start_direction.x);
+                global_transform.rotation += angle.to_degrees();
+            }
+        }
+
+        match &drag.target.parent_transform {
+            Some(parent_transform) => Some(parent_transform.relative_transform(&global_transform)),
+            None => Some(global_transform),
+        }
+    }
+}
+
+#[system]
+pub fn handle_gizmo_input(
+    #[resource] gizmo: &mut Gizmo,
+    #[resource] input_events: &Events::<KeyboardInput>,
+    #[resource] modifiers: &ModifiersState,
+    #[resource] ui_focus: &UiFocus,
+) {
+    for event in &input_events.events {
+        if let KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(keycode), .. } = event {
+            if modifiers.is_empty() && !ui_focus.wants_keyboard {
+                match keycode {
+                    VirtualKeyCode::T => gizmo.mode = GizmoMode::Translate,
+                    VirtualKeyCode::R => gizmo.mode = GizmoMode::Rotate,
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+#[system]
+#[read_component(GlobalTransform)]
+#[read_component(Parent)]
+#[read_component(mesh::PipelineParams)]
+pub fn update_gizmo_target(
+    world: &mut SubWorld,
+    #[resource] gizmo: &mut Gizmo,
+    #[resource] app_state: &AppState,
+) {
+    gizmo.target = app_state.selected_nodes.last().and_then(|node| {
+        let entry = world.entry_ref(app_state.get_node_entity(*node)?).ok()?;
+        if let Ok(params) = entry.get_component::<mesh::PipelineParams>() {
+            if app_state.locked_layers.contains(&params.layer) {
+                return None;
+            }
+        }
+        let global_transform = entry.get_component::<GlobalTransform>().ok()?.0.clone();
+        let parent_transform = match entry.get_component::<Parent>() {
+            Ok(parent) => {
+                let parent_entry = world.entry_ref(parent.0).ok()?;
+                Some(parent_entry.get_component::<GlobalTransform>().ok()?.0.clone())
+            }
+            Err(_) => None,
+        };
+        Some(GizmoTarget {
+            node: *node,
+            global_transform,
+            parent_transform,
+        })
+    });
+
+    let is_target_lost = match (&gizmo.drag, &gizmo.target) {
+        (Some(drag), Some(target)) => drag.target.node != target.node,
+        (Some(_), None) => true,
+        _ => false,
+    };
+    if is_target_lost {
+        gizmo.drag = None;
+    }
+}
@@ -0,0 +1,293 @@
+use std::mem::discriminant;
+use crate::static_data::{DataAccessor, Component};
+
+#[derive(Clone, Debug)]
+pub enum Edit {
+    // Replaces the component of the same kind on a scene node. `None` means the node doesn't have one.
+    SetComponent {
+        node: (usize, usize),
+        old: Option<Component>,
+        new: Option<Component>,
+    },
+}
+
+impl Edit {
+    pub fn set_component(data_accessor: &DataAccessor, node: (usize, usize), new_component: Component) -> Self {
+        let old = data_accessor.collections[node.0].scene_nodes[node.1].get_component(&new_component).cloned();
+        Edit::SetComponent { node, old, new: Some(new_component) }
+    }
+
+    pub fn remove_component(data_accessor: &DataAccessor, node: (usize, usize), kind: &Component) -> Self {
+        let old = data_accessor.collections[node.0].scene_nodes[node.1].get_component(kind).cloned();
+        Edit::SetComponent { node, old, new: None }
+    }
+
+    fn kind(&self) -> Option<&Component> {
+        match self {
+            Edit::SetComponent { old, new, .. } => old.as_ref().or(new.as_ref()),
+        }
+    }
+
+    fn node(&self) -> (usize, usize) {
+        match self {
+            Edit::SetComponent { node, .. } => *node,
+        }
+    }
+
+    pub fn inverse(&self) -> Self {
+        match self {
+            Edit::SetComponent { node, old, new } => Edit::SetComponent {
+                node: *node,
+                old: new.clone(),
+                new: old.clone(),
+            },
+        }
+    }
+
+    pub fn apply(&self, data_accessor: &mut DataAccessor) {
+        match self {
+            Edit::SetComponent { node, new, .. } => {
+                let kind = match self.kind() {
+                    Some(kind) => kind,
+                    None => return,
+                };
+                let collection = &mut data_accessor.collections[node.0];
+                collection.scene_nodes[node.1].replace_component(kind, new.clone());
+                if let Component::Parent(_) = kind {
+                    collection.update_children();
+                }
+            }
+        }
+    }
+
+    fn targets_same_as(&self, other: &Edit) -> bool {
+        match (self.kind(), other.kind()) {
+            (Some(kind_1), Some(kind_2)) => self.node() == other.node() && discriminant(kind_1) == discriminant(kind_2),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Transaction {
+    pub name: String,
+    pub edits: Vec<Edit>,
+
+    // Continuous edits like dragging a value are merged into the previous transaction.
+    pub merge_with_previous: bool,
+}
+
+impl Transaction {
+    pub fn new(name: &str, edits: Vec<Edit>) -> Self {
+        Self {
+            name: name.to_string(),
+            edits,
+            merge_with_previous: false,
+        }
+    }
+
+    pub fn merged(mut self) -> Self {
+        self.merge_with_previous = true;
+        self
+    }
+
+    pub fn inverse(&self) -> Self {
+        Self {
+            name: self.name.clone(),
+            edits: self.edits.iter().rev().map(|edit| edit.inverse()).collect(),
+            merge_with_previous: false,
+        }
+    }
+
+    pub fn apply(&self, data_accessor: &mut DataAccessor) {
+        for edit in &self.edits {
+            edit.apply(data_accessor);
+        }
+    }
+
+    fn merge(&mut self, transaction: Transaction) {
+        for edit in transaction.edits {
+            let existing = self.edits.iter_mut().find(|existing| existing.targets_same_as(&edit));
+            match (existing, edit) {
+                (Some(Edit::SetComponent { new, .. }), Edit::SetComponent { new: merged_new, .. }) => {
+                    *new = merged_new;
+                }
+                (None, edit) => self.edits.push(edit),
+            }
+        }
+    }
+}
+
+pub struct History {
+    undo_stack: Vec<Transaction>,
+    redo_stack: Vec<Transaction>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self {
+            undo_stack: Vec::new(),
+            redo_stack: Vec::new(),
+        }
+    }
+
+    pub fn execute(&mut self, transaction: Transaction, data_accessor: &mut DataAccessor) {
+        transaction.apply(data_accessor);
+        self.redo_stack.clear();
+        if transaction.merge_with_previous {
+            if let Some(previous) = self.undo_stack.last_mut() {
+                if previous.name == transaction.name {
+                    previous.merge(transaction);
+                    return;
+                }
+            }
+        }
+        self.undo_stack.push(transaction);
+    }
+
+    pub fn undo(&mut self, data_accessor: &mut DataAccessor) -> Option<&Transaction> {
+        let transaction = self.undo_stack.pop()?;
+        transaction.inverse().apply(data_accessor);
+        self.redo_stack.push(transaction);
+        self.redo_stack.last()
+    }
+
+    pub fn redo(&mut self, data_accessor: &mut DataAccessor) -> Option<&Transaction> {
+        let transaction = self.redo_stack.pop()?;
+        transaction.apply(data_accessor);
+        self.undo_stack.push(transaction);
+        self.undo_stack.last()
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo_stack.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::static_data::TestDirectory;
+
+    const NODE: (usize, usize) = (0, 0);
+
+    fn load(directory: &TestDirectory) -> DataAccessor {
+        directory.load_collections(&[("scene", r#"(
+            scene_nodes: [(name: "box", components: [Transform(translation: (0.0, 0.0), rotation: 0.0)])],
+        )"#)])
+    }
+
+    fn transform(x: f32) -> Component {
+        Component::Transform { translation: (x, 0.0), rotation: 0.0 }
+    }
+
+    fn move_to(data_accessor: &DataAccessor, x: f32) -> Transaction {
+        Transaction::new("Move", vec![Edit::set_component(data_accessor, NODE, transform(x))])
+    }
+
+    fn translation_x(data_accessor: &DataAccessor) -> Option<f32> {
+        match data_accessor.collections[NODE.0].scene_nodes[NODE.1].get_component(&transform(0.0)) {
+            Some(Component::Transform { translation, .. }) => Some(translation.0),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn undo_and_redo() {
+        let directory = TestDirectory::new("history-undo");
+        let mut data_accessor = load(&directory);
+        let mut history = History::new();
+        assert!(!history.can_undo() && !history.can_redo());
+
+        let transaction = move_to(&data_accessor, 1.0);
+        history.execute(transaction, &mut data_accessor);
+        let transaction = move_to(&data_accessor, 2.0);
+        history.execute(transaction, &mut data_accessor);
+        assert_eq!(translation_x(&data_accessor), Some(2.0));
+
+        assert!(history.undo(&mut data_accessor).is_some());
+        assert_eq!(translation_x(&data_accessor), Some(1.0));
+        assert!(history.undo(&mut data_accessor).is_some());
+        assert_eq!(translation_x(&data_accessor), Some(0.0));
+        assert!(history.undo(&mut data_accessor).is_none());
+
+        assert!(history.redo(&mut data_accessor).is_some());
+        assert_eq!(translation_x(&data_accessor), Some(1.0));
+        assert!(history.can_undo() && history.can_redo());
+    }
+
+    #[test]
+    fn undo_removed_component() {
+        let directory = TestDirectory::new("history-remove");
+        let mut data_accessor = load(&directory);
+        let mut history = History::new();
+
+        let edit = Edit::remove_component(&data_accessor, NODE, &transform(0.0));
+        history.execute(Transaction::new("Remove", vec![edit]), &mut data_accessor);
+        assert_eq!(translation_x(&data_accessor), None);
+        history.undo(&mut data_accessor);
+        assert_eq!(translation_x(&data_accessor), Some(0.0));
+    }
+
+    #[test]
+    fn merge_keeps_original_value() {
+        let directory = TestDirectory::new("history-merge");
+        let mut data_accessor = load(&directory);
+        let mut history = History::new();
+
+        let transaction = move_to(&data_accessor, 1.0);
+        history.execute(transaction, &mut data_accessor);
+        for x in &[2.0, 3.0] {
+            let transaction = move_to(&data_accessor, *x).merged();
+            history.execute(transaction, &mut data_accessor);
+        }
+        assert_eq!(translation_x(&data_accessor), Some(3.0));
+
+        // The merged transactions are undone at once, back to the value before the first one.
+        history.undo(&mut data_accessor);
+        assert_eq!(translation_x(&data_accessor), Some(0.0));
+        assert!(!history.can_undo());
+    }
+
+    #[test]
+    fn merge_only_with_same_name() {
+        let directory = TestDirectory::new("history-merge-name");
+        let mut data_accessor = load(&directory);
+        let mut history = History::new();
+
+        let transaction = move_to(&data_accessor, 1.0);
+        history.execute(transaction, &mut data_accessor);
+        let edit = Edit::set_component(&data_accessor, NODE, transform(2.0));
+        history.execute(Transaction::new("Drag", vec![edit]).merged(), &mut data_accessor);
+
+        history.undo(&mut data_accessor);
+        assert_eq!(translation_x(&data_accessor), Some(1.0));
+    }
+
+    #[test]
+    fn new_transaction_clears_redo() {
+        let directory = TestDirectory::new("history-clear-redo");
+        let mut data_accessor = load(&directory);
+        let mut history = History::new();
+
+        let transaction = move_to(&data_accessor, 1.0);
+        history.execute(transaction, &mut data_accessor);
+        history.undo(&mut data_accessor);
+        assert!(history.can_redo());
+
+        let transaction = move_to(&data_accessor, 2.0);
+        history.execute(transaction, &mut data_accessor);
+        assert!(!history.can_redo());
+        assert!(history.redo(&mut data_accessor).is_none());
+        assert_eq!(translation_x(&data_accessor), Some(2.0));
+    }
+}
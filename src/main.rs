@@ -1,24 +1,51 @@
 #[macro_use]
 extern crate log;
 
+use std::path::Path;
 use application::Application;
+use cli::CliCommand;
+use project::Project;
 
 mod application;
 mod wgpu_state;
 mod app_state;
 mod asset;
 mod events;
+mod file_watcher;
 mod command;
+mod history;
 mod camera;
+mod gizmo;
 mod mesh;
 mod texture;
 mod static_data;
 mod hierarchy;
 mod transform;
 mod physics;
+mod project;
 mod ui;
+mod cli;
+mod atlas_packer;
 
 fn main() {
-    let application = Application::new();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match CliCommand::from_args(&args) {
+        Some(Ok(command)) => std::process::exit(command.run()),
+        Some(Err(e)) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+        None => {}
+    }
+
+    let project_path = args.first().map_or(".", |arg| arg.as_str());
+    let project = match Project::open(Path::new(project_path)) {
+        Ok(project) => project,
+        Err(e) => {
+            eprintln!("Failed to open project: {:?}", e);
+            std::process::exit(1);
+        }
+    };
+    let application = Application::new(project);
     application.run();
 }
@@ -1,3 +1,9 @@
+use anyhow::*;
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    path::Path,
+};
 use wgpu::util::DeviceExt;
 use crate::{
     wgpu_state::WgpuState,
@@ -36,22 +42,29 @@ impl Vertex {
     }
 }
 
+// GPU resources of a mesh, shared by all entities that use the same mesh data.
 #[derive(Debug)]
-pub struct Mesh {
+pub struct MeshBuffers {
     pub vertex_buffer: wgpu::Buffer,
-    pub index_buffer: wgpu::Buffer,
+
+    // Index buffers are only created for primitives the mesh has.
+    pub index_buffer: Option<wgpu::Buffer>,
     pub num_indices: u32,
+    pub line_index_buffer: Option<wgpu::Buffer>,
+    pub num_line_indices: u32,
+
     pub texture: Handle<Texture>,
     pub texture_bind_group: wgpu::BindGroup,
 }
 
-impl Mesh {
-    pub fn new(
+impl MeshBuffers {
+    fn new(
         state: &WgpuState,
         pipeline: &Pipeline,
         textures: &mut Assets<Texture>,
-        mesh_data: &MeshData
-    ) -> Self {
+        mesh_data: &MeshData,
+        texture_path: &Path,
+    ) -> Result<Self> {
         let mut vertex_data = vec![0.0; mesh_data.vertices.len() * 5];
         for i in 0..mesh_data.vertices.len() {
             vertex_data[i * 5] = mesh_data.vertices[i].x;
@@ -65,17 +78,24 @@ impl Mesh {
             contents: bytemuck::cast_slice(&vertex_data),
             usage: wgpu::BufferUsage::VERTEX,
         });
-        let index_buffer = state.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Index Buffer"),
-            contents: bytemuck::cast_slice(&mesh_data.triangles),
-            usage: wgpu::BufferUsage::INDEX,
-        });
-        let num_indices = mesh_data.triangles.len() as u32 * 3;
-
-        let texture = {
-            let texture_path = std::path::Path::new(env!("OUT_DIR")).join("data").join(mesh_data.texture.clone());
-            Texture::get_or_load(textures, &state.device, &state.queue, texture_path).unwrap()
+        let create_index_buffer = |label, indices: &[u32]| {
+            if indices.is_empty() {
+                return None;
+            }
+            Some(state.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(label),
+                contents: bytemuck::cast_slice(indices),
+                usage: wgpu::BufferUsage::INDEX,
+            }))
         };
+        let indices = mesh_data.get_triangle_indices();
+        let index_buffer = create_index_buffer("Index Buffer", &indices);
+        let num_indices = indices.len() as u32;
+        let line_indices = mesh_data.get_line_indices();
+        let line_index_buffer = create_index_buffer("Line Index Buffer", &line_indices);
+        let num_line_indices = line_indices.len() as u32;
+
+        let texture = Texture::get_or_load(textures, &state.device, &state.queue, texture_path)?;
 
         let texture_bind_group = {
             let texture = textures.get(&texture).unwrap();
@@ -95,12 +115,85 @@ impl Mesh {
             })
         };
 
-        Self {
+        Ok(Self {
             vertex_buffer,
             index_buffer,
             num_indices,
+            line_index_buffer,
+            num_line_indices,
             texture,
             texture_bind_group,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Mesh {
+    pub buffers: Handle<MeshBuffers>,
+
+    // CPU copies of the geometry for picking.
+    pub positions: Vec<glam::Vec2>,
+    pub indices: Vec<u32>,
+}
+
+impl Mesh {
+    // The name identifies the mesh data, so that its buffers are only created once. Mesh names are relative to the
+    // collection that is spawned in, so the buffers have to be cleared when spawning another one.
+    pub fn new(
+        state: &WgpuState,
+        pipeline: &Pipeline,
+        textures: &mut Assets<Texture>,
+        mesh_buffers: &mut Assets<MeshBuffers>,
+        name: &str,
+        mesh_data: &MeshData,
+        texture_path: &Path,
+    ) -> Result<Self> {
+        let id = {
+            let mut hasher = DefaultHasher::new();
+            name.hash(&mut hasher);
+            hasher.finish()
+        };
+        let buffers = match mesh_buffers.get_handle(id) {
+            Some(buffers) => buffers,
+            None => {
+                let buffers = MeshBuffers::new(state, pipeline, textures, mesh_data, texture_path)?;
+                mesh_buffers.add(buffers, id)
+            }
+        };
+
+        let positions = mesh_data.vertices.iter().map(|vertex| glam::Vec2::new(vertex.x, vertex.y)).collect();
+        let indices = mesh_data.get_triangle_indices();
+
+        Ok(Self {
+            buffers,
+            positions,
+            indices,
+        })
+    }
+
+    pub fn contains_point(&self, point: glam::Vec2) -> bool {
+        let sign = |p1: glam::Vec2, p2: glam::Vec2, p3: glam::Vec2| {
+            (p1.x - p3.x) * (p2.y - p3.y) - (p2.x - p3.x) * (p1.y - p3.y)
+        };
+        for triangle in self.indices.chunks_exact(3) {
+            // Meshes with invalid indices aren't spawned, but their vertices may have been changed since.
+            let (v1, v2, v3) = match (
+                self.positions.get(triangle[0] as usize),
+                self.positions.get(triangle[1] as usize),
+                self.positions.get(triangle[2] as usize),
+            ) {
+                (Some(v1), Some(v2), Some(v3)) => (*v1, *v2, *v3),
+                _ => continue,
+            };
+            let d1 = sign(point, v1, v2);
+            let d2 = sign(point, v2, v3);
+            let d3 = sign(point, v3, v1);
+            let has_negative = d1 < 0.0 || d2 < 0.0 || d3 < 0.0;
+            let has_positive = d1 > 0.0 || d2 > 0.0 || d3 > 0.0;
+            if !(has_negative && has_positive) {
+                return true;
+            }
         }
+        false
     }
 }
@@ -1,4 +1,8 @@
-use std::iter;
+use std::{
+    iter,
+    mem,
+    ops::Range,
+};
 
 use legion::*;
 use legion::world::SubWorld;
@@ -6,8 +10,10 @@ use wgpu::util::DeviceExt;
 use crate::{
     wgpu_state::WgpuState,
     transform::GlobalTransform,
-    mesh,
+    mesh::{self, MeshBuffers},
+    asset::Assets,
     camera::Camera,
+    app_state::{AppState, SourceNode},
 };
 
 /*#[rustfmt::skip]
@@ -46,11 +52,16 @@ impl Uniforms {
 
 pub struct Pipeline {
     render_pipeline: wgpu::RenderPipeline,
+    line_render_pipeline: wgpu::RenderPipeline,
     pub texture_bind_group_layout: wgpu::BindGroupLayout,
     uniforms: Uniforms,
     uniform_buffer: wgpu::Buffer,
     uniform_bind_group: wgpu::BindGroup,
-    pub mesh_uniform_bind_group_layout: wgpu:: BindGroupLayout,
+
+    // Instances of all meshes in draw order, as they were written to the instance buffer.
+    instances: Vec<mesh::Instance>,
+    instance_buffer: wgpu::Buffer,
+    instance_capacity: usize,
 }
 
 impl Pipeline {
@@ -110,53 +121,121 @@ impl Pipeline {
             label: Some("uniform_bind_group"),
         });
 
-        let mesh_uniform_bind_group_layout = wgpu_state.device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            entries: &[wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStage::VERTEX,
-                ty: wgpu::BindingType::UniformBuffer {
-                    dynamic: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            }],
-            label: Some("uniform_bind_group_layout"),
-        });
+        let instance_capacity = 256;
+        let instance_buffer = Self::create_instance_buffer(wgpu_state, instance_capacity);
 
         let vs_module = wgpu_state.device.create_shader_module(wgpu::include_spirv!("../../shaders/shader.vert.spv"));
         let fs_module = wgpu_state.device.create_shader_module(wgpu::include_spirv!("../../shaders/shader.frag.spv"));
 
         let render_pipeline_layout = wgpu_state.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Render Pipeline Layout"),
-            bind_group_layouts: &[&texture_bind_group_layout, &uniform_bind_group_layout, &mesh_uniform_bind_group_layout],
+            bind_group_layouts: &[&texture_bind_group_layout, &uniform_bind_group_layout],
             push_constant_ranges: &[],
         });
 
-        let render_pipeline = wgpu_state.device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        let render_pipeline = Self::create_render_pipeline(
+            wgpu_state,
+            &render_pipeline_layout,
+            &vs_module,
+            &fs_module,
+            wgpu::PrimitiveTopology::TriangleList,
+        );
+        let line_render_pipeline = Self::create_render_pipeline(
+            wgpu_state,
+            &render_pipeline_layout,
+            &vs_module,
+            &fs_module,
+            wgpu::PrimitiveTopology::LineList,
+        );
+
+        Self {
+            render_pipeline,
+            line_render_pipeline,
+            texture_bind_group_layout,
+            uniforms,
+            uniform_buffer,
+            uniform_bind_group,
+            instances: Vec::new(),
+            instance_buffer,
+            instance_capacity,
+        }
+    }
+
+    fn create_instance_buffer(wgpu_state: &WgpuState, capacity: usize) -> wgpu::Buffer {
+        wgpu_state.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Instance Buffer"),
+            size: (capacity * mem::size_of::<mesh::Instance>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    // Only the ranges of instances that changed since the last frame are written to the buffer.
+    fn update_instances(&mut self, wgpu_state: &WgpuState, instances: Vec<mesh::Instance>) {
+        if instances.len() > self.instance_capacity {
+            self.instance_capacity = instances.len().next_power_of_two();
+            self.instance_buffer = Self::create_instance_buffer(wgpu_state, self.instance_capacity);
+            self.instances.clear();
+        }
+
+        let is_changed = |i: usize| self.instances.get(i) != Some(&instances[i]);
+        let mut i = 0;
+        while i < instances.len() {
+            if !is_changed(i) {
+                i += 1;
+                continue;
+            }
+            let start = i;
+            while i < instances.len() && is_changed(i) {
+                i += 1;
+            }
+            wgpu_state.queue.write_buffer(
+                &self.instance_buffer,
+                (start * mem::size_of::<mesh::Instance>()) as wgpu::BufferAddress,
+                bytemuck::cast_slice(&instances[start..i]),
+            );
+        }
+        self.instances = instances;
+    }
+
+    fn create_render_pipeline(
+        wgpu_state: &WgpuState,
+        layout: &wgpu::PipelineLayout,
+        vs_module: &wgpu::ShaderModule,
+        fs_module: &wgpu::ShaderModule,
+        primitive_topology: wgpu::PrimitiveTopology,
+    ) -> wgpu::RenderPipeline {
+        // Lines have no facing, so they must not be culled.
+        let cull_mode = match primitive_topology {
+            wgpu::PrimitiveTopology::TriangleList => wgpu::CullMode::Back,
+            _ => wgpu::CullMode::None,
+        };
+        wgpu_state.device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
             label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
+            layout: Some(layout),
             vertex_stage: wgpu::ProgrammableStageDescriptor {
-                module: &vs_module,
+                module: vs_module,
                 entry_point: "main",
             },
             fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
-                module: &fs_module,
+                module: fs_module,
                 entry_point: "main",
             }),
             rasterization_state: Some(wgpu::RasterizationStateDescriptor {
                 front_face: wgpu::FrontFace::Ccw,
-                cull_mode: wgpu::CullMode::Back,
+                cull_mode,
                 depth_bias: 0,
                 depth_bias_slope_scale: 0.0,
                 depth_bias_clamp: 0.0,
                 clamp_depth: false,
             }),
-            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+            primitive_topology,
             color_states: &[wgpu::ColorStateDescriptor {
                 format: wgpu_state.sc_desc.format,
                 //color_blend: wgpu::BlendDescriptor::REPLACE,
                 color_blend: wgpu::BlendDescriptor {
-                    src_factor: wgpu::BlendFactor::SrcAlpha,
+                    // Textures are loaded with premultiplied alpha.
+                    src_factor: wgpu::BlendFactor::One,
                     dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
                     operation: wgpu::BlendOperation::Add,
                 },
@@ -166,21 +245,12 @@ impl Pipeline {
             depth_stencil_state: None,
             vertex_state: wgpu::VertexStateDescriptor {
                 index_format: wgpu::IndexFormat::Uint32,
-                vertex_buffers: &[mesh::Vertex::desc()],
+                vertex_buffers: &[mesh::Vertex::desc(), mesh::Instance::desc()],
             },
             sample_count: 1,
             sample_mask: !0,
             alpha_to_coverage_enabled: false,
-        });
-
-        Self {
-            render_pipeline,
-            texture_bind_group_layout,
-            uniforms,
-            uniform_buffer,
-            uniform_bind_group,
-            mesh_uniform_bind_group_layout,
-        }
+        })
     }
 }
 
@@ -201,15 +271,17 @@ pub fn update_camera_buffer(
 #[system]
 #[read_component(GlobalTransform)]
 #[read_component(mesh::Mesh)]
+#[read_component(SourceNode)]
 #[write_component(mesh::PipelineParams)]
 pub fn render_meshes(
     world: &mut SubWorld,
     #[resource] state: &mut WgpuState,
     #[resource] pipeline: &mut Pipeline,
+    #[resource] mesh_buffers: &Assets<MeshBuffers>,
     #[resource] camera: &Camera,
+    #[resource] app_state: &AppState,
 ) {
     if let None = state.current_frame { return; }
-    let frame = &state.current_frame.as_ref().unwrap().output;
 
     // Update camera buffer.
     pipeline.uniforms.update_view_proj(&camera);
@@ -219,15 +291,42 @@ pub fn render_meshes(
         bytemuck::cast_slice(&[pipeline.uniforms]),
     );
 
+    // Meshes are sorted by layer and order first. Within the same order they are sorted by texture and buffers, so that
+    // each run of entities with the same buffers can be drawn with one instanced draw call, and the texture only needs
+    // to be bound when it changes.
+    let mut batches: Vec<(&MeshBuffers, Range<u32>)> = Vec::new();
     {
         let mut query =
-            <(&GlobalTransform, &mut mesh::PipelineParams)>::query();
-                //.filter(maybe_changed::<Transform2D>()); // Doesn't seem to do anything.
-        for (transform, params) in query.iter_mut(world) {
-            params.update_from_transform(&transform.0, &state.queue);
+            <(&GlobalTransform, &mesh::Mesh, &mut mesh::PipelineParams, Option<&SourceNode>)>::query();
+        let mut draws = Vec::new();
+        for (transform, mesh, params, source_node) in query.iter_mut(world) {
+            let buffers = match mesh_buffers.get(&mesh.buffers) {
+                Some(buffers) => buffers,
+                None => continue,
+            };
+            if app_state.hidden_layers.contains(&params.layer) {
+                params.draw_index = None;
+                continue;
+            }
+            let is_selected = source_node.is_some_and(|source_node| app_state.selected_nodes.contains(&source_node.0));
+            params.update_from_transform(&transform.0, is_selected);
+            draws.push(((params.layer, params.order, *buffers.texture.id, *mesh.buffers.id), buffers, params));
+        }
+        draws.sort_by_key(|(key, _, _)| *key);
+
+        let mut instances = Vec::with_capacity(draws.len());
+        for (i, (_, buffers, params)) in draws.into_iter().enumerate() {
+            params.draw_index = Some(i);
+            instances.push(params.instance);
+            match batches.last_mut() {
+                Some((batch_buffers, range)) if std::ptr::eq(*batch_buffers, buffers) => range.end += 1,
+                _ => batches.push((buffers, i as u32..i as u32 + 1)),
+            }
         }
+        pipeline.update_instances(state, instances);
     }
 
+    let frame = &state.current_frame.as_ref().unwrap().output;
     let mut encoder = state
         .device
         .create_command_encoder(&wgpu::CommandEncoderDescriptor {
@@ -254,14 +353,26 @@ pub fn render_meshes(
 
         render_pass.set_pipeline(&pipeline.render_pipeline);
         render_pass.set_bind_group(1, &pipeline.uniform_bind_group, &[]);
+        render_pass.set_vertex_buffer(1, pipeline.instance_buffer.slice(..));
 
-        let mut query = <(&mesh::Mesh, &mesh::PipelineParams)>::query();
-        for (mesh, params) in query.iter(world) {
-            render_pass.set_bind_group(2, &params.uniform_bind_group, &[]);
-            render_pass.set_bind_group(0, &mesh.texture_bind_group, &[]);
-            render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
-            render_pass.set_index_buffer(mesh.index_buffer.slice(..));
-            render_pass.draw_indexed(0..mesh.num_indices, 0, 0..1);
+        // Both pipelines share the layout, so the bind groups stay valid when switching between them.
+        let mut bound_texture = None;
+        for (buffers, instances) in batches {
+            if bound_texture != Some(*buffers.texture.id) {
+                render_pass.set_bind_group(0, &buffers.texture_bind_group, &[]);
+                bound_texture = Some(*buffers.texture.id);
+            }
+            render_pass.set_vertex_buffer(0, buffers.vertex_buffer.slice(..));
+            if let Some(index_buffer) = &buffers.index_buffer {
+                render_pass.set_index_buffer(index_buffer.slice(..));
+                render_pass.draw_indexed(0..buffers.num_indices, 0, instances.clone());
+            }
+            if let Some(line_index_buffer) = &buffers.line_index_buffer {
+                render_pass.set_pipeline(&pipeline.line_render_pipeline);
+                render_pass.set_index_buffer(line_index_buffer.slice(..));
+                render_pass.draw_indexed(0..buffers.num_line_indices, 0, instances);
+                render_pass.set_pipeline(&pipeline.render_pipeline);
+            }
         }
     }
 
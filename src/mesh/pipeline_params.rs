@@ -1,68 +1,88 @@
-use crate::
-{
-    wgpu_state::WgpuState,
-    mesh::Pipeline,
-    transform::Transform2D,
-};
-use wgpu::util::DeviceExt;
+use crate::transform::Transform2D;
 
 #[repr(C)]
-#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-struct Uniforms {
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Instance {
     model_matrix: [f32; 16],
+    highlight: [f32; 4],
+    color: [f32; 4],
 }
 
-impl Uniforms {
-    fn new(model_matrix: &glam::Mat4) -> Self {
-        Self {
-            model_matrix: model_matrix.to_cols_array(),
+impl Instance {
+    pub fn desc<'a>() -> wgpu::VertexBufferDescriptor<'a> {
+        use std::mem;
+        const COLUMN_SIZE: wgpu::BufferAddress = mem::size_of::<[f32; 4]>() as wgpu::BufferAddress;
+        wgpu::VertexBufferDescriptor {
+            stride: mem::size_of::<Instance>() as wgpu::BufferAddress,
+            step_mode: wgpu::InputStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttributeDescriptor {
+                    offset: 0,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float4,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    offset: COLUMN_SIZE,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Float4,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    offset: COLUMN_SIZE * 2,
+                    shader_location: 4,
+                    format: wgpu::VertexFormat::Float4,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    offset: COLUMN_SIZE * 3,
+                    shader_location: 5,
+                    format: wgpu::VertexFormat::Float4,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    offset: COLUMN_SIZE * 4,
+                    shader_location: 6,
+                    format: wgpu::VertexFormat::Float4,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    offset: COLUMN_SIZE * 5,
+                    shader_location: 7,
+                    format: wgpu::VertexFormat::Float4,
+                },
+            ],
         }
     }
-
-    fn set_model_matrix(&mut self, model_matrix: &glam::Mat4) {
-        self.model_matrix = model_matrix.to_cols_array();
-    }
 }
 
+// Per entity data of the mesh pipeline. The instances of all entities are collected into one buffer when rendering.
 pub struct PipelineParams {
-    uniforms: Uniforms,
-    uniform_buffer: wgpu::Buffer,
-    pub uniform_bind_group: wgpu::BindGroup,
-}
+    pub instance: Instance,
 
-impl PipelineParams {
-    pub fn new(wgpu_state: &WgpuState, pipeline: &Pipeline, model_matrix: &glam::Mat4) -> Self {
-        let uniforms = Uniforms::new(model_matrix);
+    // Index of the render layer in the collection, and the order within the layer.
+    pub layer: usize,
+    pub order: i32,
 
-        let uniform_buffer = wgpu_state.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Uniform Buffer"),
-            contents: bytemuck::cast_slice(&[uniforms]),
-            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
-        });
-
-        let uniform_bind_group = wgpu_state.device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &pipeline.mesh_uniform_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::Buffer(uniform_buffer.slice(..)),
-            }],
-            label: Some("uniform_bind_group"),
-        });
+    // Position of the entity in the last frame's draw order, `None` if it wasn't drawn.
+    pub draw_index: Option<usize>,
+}
 
+impl PipelineParams {
+    pub fn new(model_matrix: &glam::Mat4) -> Self {
         Self {
-            uniforms,
-            uniform_buffer,
-            uniform_bind_group,
+            instance: Instance {
+                model_matrix: model_matrix.to_cols_array(),
+                highlight: [0.0; 4],
+                color: [1.0; 4],
+            },
+            layer: 0,
+            order: 0,
+            draw_index: None,
         }
     }
 
-    pub fn update_from_transform(&mut self, transform: &Transform2D, queue: &wgpu::Queue) {
-        self.uniforms.set_model_matrix(&transform.build_matrix());
+    pub fn set_color(&mut self, color: [f32; 4]) {
+        self.instance.color = color;
+    }
 
-        queue.write_buffer(
-            &self.uniform_buffer,
-            0,
-            bytemuck::cast_slice(&[self.uniforms]),
-        );
+    pub fn update_from_transform(&mut self, transform: &Transform2D, is_highlighted: bool) {
+        self.instance.model_matrix = transform.build_matrix().to_cols_array();
+        self.instance.highlight[0] = if is_highlighted { 1.0 } else { 0.0 };
     }
 }
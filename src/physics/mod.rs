@@ -1,38 +1,224 @@
-use legion::*;
+use legion::{*, world::SubWorld};
 use rapier2d::{
-    na::Vector2,
-    dynamics::{JointSet, RigidBodySet, IntegrationParameters},
-    geometry::{BroadPhase, NarrowPhase, ColliderSet},
+    na::{Isometry2, Point2, Unit, Vector2},
+    dynamics::{
+        BallJoint, BodyStatus, FixedJoint, JointParams, JointSet, MassProperties, PrismaticJoint, RigidBodyBuilder,
+        RigidBodySet, IntegrationParameters,
+    },
+    geometry::{BroadPhase, NarrowPhase, ColliderBuilder, ColliderSet, InteractionGroups},
     pipeline::PhysicsPipeline,
 };
 
-use crate::transform::LocalTransform;
+use crate::{
+    static_data::{self, Collider, Joint, JointKind, PhysicsSettings, RigidBody, RigidBodyStatus, Shape},
+    transform::{LocalTransform, Transform2D},
+};
+
+// Physics settings of the current scene, converted for rapier. Set when a scene is spawned.
+pub struct PhysicsParameters {
+    pub gravity: Vector2<f32>,
+    pub max_substeps: u32,
+    pub pixels_per_meter: f32,
+    pub integration_parameters: IntegrationParameters,
+}
+
+impl PhysicsParameters {
+    pub fn new(settings: &PhysicsSettings) -> Self {
+        let mut integration_parameters = IntegrationParameters::default();
+        integration_parameters.set_dt(settings.timestep);
+        integration_parameters.max_velocity_iterations = settings.solver_iterations;
+        Self {
+            gravity: Vector2::new(settings.gravity.0, settings.gravity.1),
+            max_substeps: settings.max_substeps,
+            pixels_per_meter: settings.pixels_per_meter,
+            integration_parameters,
+        }
+    }
+}
+
+// The parts of rapier that are only used to step the simulation.
+pub struct PhysicsStepper {
+    pipeline: PhysicsPipeline,
+    broad_phase: BroadPhase,
+    narrow_phase: NarrowPhase,
+}
 
-pub struct TimeSinceLastPhysicsUpdate(pub f32);
+impl PhysicsStepper {
+    pub fn new() -> Self {
+        Self {
+            pipeline: PhysicsPipeline::new(),
+            broad_phase: BroadPhase::new(),
+            narrow_phase: NarrowPhase::new(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SimulationMode {
+    // Physics is frozen, so that the scene shows the authored transforms.
+    Edit,
+    Playing,
+    Paused,
+}
+
+// State of the rigid bodies when play mode was entered.
+struct RigidBodySnapshot {
+    handle: rapier2d::data::arena::Index,
+    position: Isometry2<f32>,
+    linear_velocity: Vector2<f32>,
+    angular_velocity: f32,
+    is_sleeping: bool,
+}
+
+pub struct Simulation {
+    pub mode: SimulationMode,
+    pub time_since_last_update: f32,
+    // Single steps requested while paused.
+    pending_steps: u32,
+    snapshot: Vec<RigidBodySnapshot>,
+}
+
+impl Simulation {
+    pub fn new() -> Self {
+        Self {
+            mode: SimulationMode::Edit,
+            time_since_last_update: 0.0,
+            pending_steps: 0,
+            snapshot: Vec::new(),
+        }
+    }
+
+    pub fn play(&mut self, rigid_body_set: &RigidBodySet) {
+        if self.mode == SimulationMode::Edit {
+            self.take_snapshot(rigid_body_set);
+        }
+        self.mode = SimulationMode::Playing;
+    }
+
+    pub fn pause(&mut self) {
+        if self.mode == SimulationMode::Playing {
+            self.mode = SimulationMode::Paused;
+        }
+    }
+
+    // Pauses the simulation if necessary and advances it by one step.
+    pub fn step(&mut self, rigid_body_set: &RigidBodySet) {
+        if self.mode == SimulationMode::Edit {
+            self.take_snapshot(rigid_body_set);
+        }
+        self.mode = SimulationMode::Paused;
+        self.pending_steps += 1;
+    }
+
+    // Restores the snapshot and goes back to edit mode.
+    pub fn reset(&mut self, rigid_body_set: &mut RigidBodySet) {
+        for body_snapshot in self.snapshot.drain(..) {
+            if let Some(rigid_body) = rigid_body_set.get_mut(body_snapshot.handle) {
+                rigid_body.set_position(body_snapshot.position, true);
+                rigid_body.set_linvel(body_snapshot.linear_velocity, true);
+                rigid_body.set_angvel(body_snapshot.angular_velocity, true);
+                if body_snapshot.is_sleeping {
+                    rigid_body.sleep();
+                }
+            }
+        }
+        self.discard_snapshot();
+    }
+
+    // Goes back to edit mode without restoring anything, e.g. because the scene has been respawned.
+    pub fn discard_snapshot(&mut self) {
+        self.mode = SimulationMode::Edit;
+        self.pending_steps = 0;
+        self.snapshot.clear();
+    }
+
+    // Moving a rigid body outside of edit mode also moves it in the snapshot, so that resetting keeps the edit.
+    pub fn set_snapshot_position(&mut self, handle: rapier2d::data::arena::Index, position: Isometry2<f32>) {
+        if let Some(body_snapshot) = self.snapshot.iter_mut().find(|body_snapshot| body_snapshot.handle == handle) {
+            body_snapshot.position = position;
+        }
+    }
+
+    fn take_snapshot(&mut self, rigid_body_set: &RigidBodySet) {
+        self.snapshot = rigid_body_set.iter()
+            .map(|(handle, rigid_body)| RigidBodySnapshot {
+                handle,
+                position: *rigid_body.position(),
+                linear_velocity: *rigid_body.linvel(),
+                angular_velocity: rigid_body.angvel(),
+                is_sleeping: rigid_body.is_sleeping(),
+            })
+            .collect();
+    }
+}
 
 pub struct RigidBodyHandle(pub rapier2d::data::arena::Index);
-pub struct ColliderHandle(pub rapier2d::data::arena::Index);
+// Compound shapes consist of several colliders.
+pub struct ColliderHandles(pub Vec<rapier2d::data::arena::Index>);
+
+// Rapier applies the same gravity to all rigid bodies, so the difference is applied as a force before each step.
+pub struct GravityScale(pub f32);
+
+// Rapier has no joint motors yet, so they are applied as impulses before each step.
+pub struct Motor {
+    pub body1: rapier2d::data::arena::Index,
+    pub body2: rapier2d::data::arena::Index,
+    // Axis of a prismatic joint in the local space of the first body. Motors without one drive the rotation.
+    pub axis: Option<Unit<Vector2<f32>>>,
+    pub target_velocity: f32,
+    pub max_impulse: f32,
+}
+
+// Motors of the joints an entity is the second node of.
+pub struct JointMotors(pub Vec<Motor>);
 
 #[system]
+#[read_component(RigidBodyHandle)]
+#[read_component(GravityScale)]
+#[read_component(JointMotors)]
 pub fn update_physics(
-    #[resource] time_since_last_physics_update: &mut TimeSinceLastPhysicsUpdate,
-    #[resource] pipeline: &mut PhysicsPipeline,
-    #[resource] integration_parameters: &IntegrationParameters,
-    #[resource] broad_phase: &mut BroadPhase,
-    #[resource] narrow_phase: &mut NarrowPhase,
+    world: &SubWorld,
+    #[resource] simulation: &mut Simulation,
+    #[resource] stepper: &mut PhysicsStepper,
+    #[resource] parameters: &PhysicsParameters,
     #[resource] rigid_body_set: &mut RigidBodySet,
     #[resource] collider_set: &mut ColliderSet,
     #[resource] joint_set: &mut JointSet,
 ) {
-    let interval = 1.0 / 60.0;
-    while time_since_last_physics_update.0 > interval {
-        time_since_last_physics_update.0 -= interval;
+    let interval = parameters.integration_parameters.dt();
+    let gravity = parameters.gravity;
+    let mut query = <(&RigidBodyHandle, &GravityScale)>::query();
+    let mut motor_query = <&JointMotors>::query();
+    let mut num_steps = std::mem::take(&mut simulation.pending_steps);
+    if simulation.mode == SimulationMode::Playing {
+        while simulation.time_since_last_update > interval {
+            simulation.time_since_last_update -= interval;
+            num_steps += 1;
+        }
+    }
+    else {
+        simulation.time_since_last_update = 0.0;
+    }
+    num_steps = num_steps.min(parameters.max_substeps);
 
-        pipeline.step(
-            &Vector2::new(0.0, -9.81),
-            integration_parameters,
-            broad_phase,
-            narrow_phase,
+    for _ in 0..num_steps {
+        for (rigid_body_handle, gravity_scale) in query.iter(world) {
+            if let Some(rigid_body) = rigid_body_set.get_mut(rigid_body_handle.0) {
+                let mass = rigid_body.mass();
+                rigid_body.apply_force(gravity * (gravity_scale.0 - 1.0) * mass, false);
+            }
+        }
+        for joint_motors in motor_query.iter(world) {
+            for motor in &joint_motors.0 {
+                apply_motor(motor, rigid_body_set);
+            }
+        }
+
+        stepper.pipeline.step(
+            &gravity,
+            &parameters.integration_parameters,
+            &mut stepper.broad_phase,
+            &mut stepper.narrow_phase,
             rigid_body_set,
             collider_set,
             joint_set,
@@ -48,9 +234,222 @@ pub fn copy_transforms_from_rigid_bodies(
     transform: &mut LocalTransform,
     rigid_body_handle: &RigidBodyHandle,
     #[resource] rigid_body_set: &RigidBodySet,
+    #[resource] parameters: &PhysicsParameters,
 ) {
     let rigid_body = rigid_body_set.get(rigid_body_handle.0).unwrap();
     let position = rigid_body.position();
-    transform.0.translation = glam::Vec3::new(position.translation.vector.x * 64.0, position.translation.vector.y * 64.0, 0.0);
+    let translation = position.translation.vector * parameters.pixels_per_meter;
+    transform.0.translation = glam::Vec3::new(translation.x, translation.y, 0.0);
     transform.0.rotation = position.rotation.angle().to_degrees();
 }
+
+pub fn build_rigid_body(rigid_body: &RigidBody, transform: &Transform2D, pixels_per_meter: f32) -> RigidBodyBuilder {
+    let status = match rigid_body.status {
+        RigidBodyStatus::Static => BodyStatus::Static,
+        RigidBodyStatus::Dynamic => BodyStatus::Dynamic,
+        RigidBodyStatus::Kinematic => BodyStatus::Kinematic,
+    };
+    let mut builder = RigidBodyBuilder::new(status)
+        .translation(transform.translation.x / pixels_per_meter, transform.translation.y / pixels_per_meter)
+        .rotation(transform.rotation.to_radians())
+        .linear_damping(rigid_body.linear_damping)
+        .angular_damping(rigid_body.angular_damping)
+        .linvel(rigid_body.linear_velocity.0 / pixels_per_meter, rigid_body.linear_velocity.1 / pixels_per_meter)
+        .angvel(rigid_body.angular_velocity.to_radians())
+        .can_sleep(rigid_body.can_sleep)
+        .sleeping(rigid_body.sleeping);
+    if let Some(mass) = rigid_body.mass {
+        builder = builder.mass(mass, false);
+    }
+    if rigid_body.fixed_rotation {
+        builder = builder.lock_rotations();
+    }
+    builder
+}
+
+// Pushes the bodies apart along the axis, or turns them in opposite directions, so that their relative velocity reaches
+// the target.
+fn apply_motor(motor: &Motor, rigid_body_set: &mut RigidBodySet) {
+    let (body1, body2) = match (rigid_body_set.get(motor.body1), rigid_body_set.get(motor.body2)) {
+        (Some(body1), Some(body2)) => (body1, body2),
+        _ => return,
+    };
+    match motor.axis {
+        Some(axis) => {
+            let axis = body1.position().rotation * axis.into_inner();
+            let inverse_mass = body1.mass_properties().inv_mass + body2.mass_properties().inv_mass;
+            if inverse_mass == 0.0 {
+                return;
+            }
+            let velocity = (body2.linvel() - body1.linvel()).dot(&axis);
+            let impulse = ((motor.target_velocity - velocity) / inverse_mass).max(-motor.max_impulse).min(motor.max_impulse);
+            rigid_body_set.get_mut(motor.body1).unwrap().apply_impulse(-axis * impulse, true);
+            rigid_body_set.get_mut(motor.body2).unwrap().apply_impulse(axis * impulse, true);
+        }
+        None => {
+            let inverse_inertia = body1.mass_properties().inv_principal_inertia_sqrt.powi(2)
+                + body2.mass_properties().inv_principal_inertia_sqrt.powi(2);
+            if inverse_inertia == 0.0 {
+                return;
+            }
+            let velocity = body2.angvel() - body1.angvel();
+            let impulse = ((motor.target_velocity - velocity) / inverse_inertia).max(-motor.max_impulse).min(motor.max_impulse);
+            rigid_body_set.get_mut(motor.body1).unwrap().apply_torque_impulse(-impulse, true);
+            rigid_body_set.get_mut(motor.body2).unwrap().apply_torque_impulse(impulse, true);
+        }
+    }
+}
+
+pub fn build_joint(
+    joint: &Joint,
+    body1: rapier2d::data::arena::Index,
+    body2: rapier2d::data::arena::Index,
+    pixels_per_meter: f32,
+) -> (JointParams, Option<Motor>) {
+    let point = |(x, y): (f32, f32)| Point2::new(x / pixels_per_meter, y / pixels_per_meter);
+    let build_motor = |motor: &Option<static_data::JointMotor>, axis: Option<Unit<Vector2<f32>>>, scale: f32| {
+        motor.map(|motor| Motor {
+            body1,
+            body2,
+            axis,
+            target_velocity: motor.target_velocity * scale,
+            max_impulse: motor.max_impulse,
+        })
+    };
+    match &joint.kind {
+        JointKind::Ball { anchor1, anchor2, motor } => {
+            let params = BallJoint::new(point(*anchor1), point(*anchor2)).into();
+            (params, build_motor(motor, None, 1f32.to_radians()))
+        }
+        JointKind::Fixed { anchor1, rotation1, anchor2, rotation2 } => {
+            let params = FixedJoint::new(
+                get_isometry(*anchor1, *rotation1, pixels_per_meter),
+                get_isometry(*anchor2, *rotation2, pixels_per_meter),
+            ).into();
+            (params, None)
+        }
+        JointKind::Prismatic { anchor1, anchor2, axis, limits, motor } => {
+            let axis = Unit::new_normalize(Vector2::new(axis.0, axis.1));
+            let mut prismatic_joint = PrismaticJoint::new(point(*anchor1), axis, point(*anchor2), axis);
+            if let Some((min, max)) = limits {
+                prismatic_joint.limits_enabled = true;
+                prismatic_joint.limits = [min / pixels_per_meter, max / pixels_per_meter];
+            }
+            (prismatic_joint.into(), build_motor(motor, Some(axis), 1.0 / pixels_per_meter))
+        }
+    }
+}
+
+// Builders for the colliders that make up the shape, positioned relative to the rigid body. Rapier has no convex
+// polygons, so they are split into triangles. Triangles and segments don't add mass to the rigid body.
+pub fn build_colliders(collider: &Collider, pixels_per_meter: f32) -> Vec<ColliderBuilder> {
+    let mut builders = Vec::new();
+    let position = get_isometry(collider.offset, collider.rotation, pixels_per_meter);
+    add_shape_colliders(&collider.shape, position, pixels_per_meter, &mut builders);
+    builders.into_iter()
+        .map(|builder| {
+            let builder = builder
+                .friction(collider.friction)
+                .restitution(collider.restitution)
+                .sensor(collider.sensor)
+                .collision_groups(get_interaction_groups(&collider.collision_groups))
+                .solver_groups(get_interaction_groups(&collider.solver_groups));
+            match collider.density {
+                Some(density) => builder.density(density),
+                None => builder,
+            }
+        })
+        .collect()
+}
+
+// Rapier computes no mass for the triangles that convex polygons are split into, so it's computed here and added to
+// the rigid body, unless the rigid body replaces the mass of its colliders. Polylines have no area, so they stay
+// massless.
+pub fn add_polygon_mass(
+    rigid_body: &mut rapier2d::dynamics::RigidBody,
+    rigid_body_data: &RigidBody,
+    collider: &Collider,
+    pixels_per_meter: f32,
+) {
+    if rigid_body_data.mass.is_some() {
+        return;
+    }
+    let position = get_isometry(collider.offset, collider.rotation, pixels_per_meter);
+    let density = collider.density.unwrap_or(1.0);
+    let polygon_mass_properties = get_polygon_mass_properties(&collider.shape, position, density, pixels_per_meter);
+    if polygon_mass_properties.inv_mass == 0.0 {
+        return;
+    }
+    let mut mass_properties = *rigid_body.mass_properties() + polygon_mass_properties;
+    if rigid_body_data.fixed_rotation {
+        mass_properties.inv_principal_inertia_sqrt = 0.0;
+    }
+    rigid_body.set_mass_properties(mass_properties, false);
+}
+
+fn get_polygon_mass_properties(shape: &Shape, position: Isometry2<f32>, density: f32, pixels_per_meter: f32) -> MassProperties {
+    let point = |(x, y): (f32, f32)| position * Point2::new(x / pixels_per_meter, y / pixels_per_meter);
+    let mut mass_properties = MassProperties::new(Point2::origin(), 0.0, 0.0);
+    match shape {
+        Shape::ConvexPolygon(points) => {
+            for i in 1..points.len().saturating_sub(1) {
+                let (a, b, c) = (point(points[0]), point(points[i]), point(points[i + 1]));
+                let area = ((b - a).perp(&(c - a)) / 2.0).abs();
+                if area == 0.0 {
+                    continue;
+                }
+                let mass = area * density;
+                let center = Point2::from((a.coords + b.coords + c.coords) / 3.0);
+                // The polar moment of inertia of a triangle around its centroid.
+                let inertia = mass * ((a - b).norm_squared() + (b - c).norm_squared() + (c - a).norm_squared()) / 36.0;
+                mass_properties += MassProperties::new(center, mass, inertia);
+            }
+        }
+        Shape::Compound(parts) => {
+            for part in parts {
+                let part_position = position * get_isometry(part.offset, part.rotation, pixels_per_meter);
+                mass_properties += get_polygon_mass_properties(&part.shape, part_position, density, pixels_per_meter);
+            }
+        }
+        _ => {}
+    }
+    mass_properties
+}
+
+fn get_interaction_groups(groups: &static_data::InteractionGroups) -> InteractionGroups {
+    InteractionGroups::new(groups.memberships, groups.filter)
+}
+
+fn add_shape_colliders(shape: &Shape, position: Isometry2<f32>, pixels_per_meter: f32, builders: &mut Vec<ColliderBuilder>) {
+    let point = |(x, y): (f32, f32)| Point2::new(x / pixels_per_meter, y / pixels_per_meter);
+    let length = |length: &f32| length / pixels_per_meter;
+    match shape {
+        Shape::Cuboid(hx, hy) => builders.push(ColliderBuilder::cuboid(length(hx), length(hy)).position(position)),
+        Shape::Ball(radius) => builders.push(ColliderBuilder::ball(length(radius)).position(position)),
+        Shape::Capsule(half_height, radius) => {
+            builders.push(ColliderBuilder::capsule_y(length(half_height), length(radius)).position(position))
+        }
+        Shape::ConvexPolygon(points) => {
+            for i in 1..points.len().saturating_sub(1) {
+                let builder = ColliderBuilder::triangle(point(points[0]), point(points[i]), point(points[i + 1]));
+                builders.push(builder.position(position));
+            }
+        }
+        Shape::Polyline(points) => {
+            for segment in points.windows(2) {
+                builders.push(ColliderBuilder::segment(point(segment[0]), point(segment[1])).position(position));
+            }
+        }
+        Shape::Compound(parts) => {
+            for part in parts {
+                let part_position = position * get_isometry(part.offset, part.rotation, pixels_per_meter);
+                add_shape_colliders(&part.shape, part_position, pixels_per_meter, builders);
+            }
+        }
+    }
+}
+
+// Converts a position in pixels and a rotation in degrees.
+pub fn get_isometry(offset: (f32, f32), rotation: f32, pixels_per_meter: f32) -> Isometry2<f32> {
+    Isometry2::new(Vector2::new(offset.0 / pixels_per_meter, offset.1 / pixels_per_meter), rotation.to_radians())
+}
@@ -0,0 +1,75 @@
+use anyhow::*;
+use serde::Deserialize;
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+const PROJECT_FILE_NAME: &str = "project.ron";
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct ProjectFile {
+    name: String,
+    data_directory: String,
+}
+
+impl Default for ProjectFile {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            data_directory: "data".to_string(),
+        }
+    }
+}
+
+pub struct Project {
+    pub name: String,
+    pub data_directory: PathBuf,
+}
+
+impl Project {
+    // The path can either point to a project file, or to a directory that optionally contains one. Without a project
+    // file the data is expected in the "data" subdirectory.
+    pub fn open(path: &Path) -> Result<Self> {
+        let (root, project_file_path) = if path.is_file() {
+            let root = path.parent().context("Project file has no parent directory")?.to_path_buf();
+            (root, Some(path.to_path_buf()))
+        }
+        else if path.is_dir() {
+            let project_file_path = path.join(PROJECT_FILE_NAME);
+            let project_file_path = if project_file_path.is_file() { Some(project_file_path) } else { None };
+            (path.to_path_buf(), project_file_path)
+        }
+        else {
+            bail!("Project path {} does not exist", path.display());
+        };
+
+        let project_file = match &project_file_path {
+            Some(project_file_path) => {
+                let file = File::open(project_file_path)?;
+                ron::de::from_reader(file)
+                    .with_context(|| format!("Failed to parse {}", project_file_path.display()))?
+            }
+            None => ProjectFile::default(),
+        };
+
+        let name = if project_file.name.is_empty() {
+            root.canonicalize()
+                .ok()
+                .and_then(|path| path.file_name().map(|name| name.to_string_lossy().to_string()))
+                .unwrap_or_default()
+        } else {
+            project_file.name
+        };
+        let data_directory = root.join(&project_file.data_directory);
+        if !data_directory.is_dir() {
+            bail!("Data directory {} does not exist", data_directory.display());
+        }
+
+        Ok(Self {
+            name,
+            data_directory,
+        })
+    }
+}
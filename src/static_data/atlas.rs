@@ -0,0 +1,370 @@
+use serde::{Serialize, Deserialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File},
+    path::Path,
+};
+use anyhow::{Context, Result};
+use crate::static_data::*;
+
+// Named sub-rectangles of a texture. Atlases are loaded from TexturePacker JSON files in the data directory and are
+// named after the file.
+#[derive(Debug, Clone)]
+pub struct TextureAtlas {
+    pub name: String,
+
+    // Relative to the data directory, like the textures of meshes.
+    pub texture: String,
+    pub width: u32,
+    pub height: u32,
+    pub regions: Vec<AtlasRegion>,
+    pub region_indices: HashMap<String, usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct AtlasRegion {
+    pub name: String,
+
+    // Position in the atlas texture in pixels. The size is that of the unrotated region.
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+
+    // Rotated regions are stored turned 90 degrees clockwise.
+    pub rotated: bool,
+
+    // Position of the region in the original image, which is larger if transparent borders were trimmed.
+    pub offset: (u32, u32),
+    pub source_size: (u32, u32),
+
+    // Relative to the original image, with (0, 0) in the top left corner.
+    pub pivot: (f32, f32),
+}
+
+// Generates a mesh from an atlas region when a collection is loaded.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Sprite {
+    pub mesh_name: String,
+    pub atlas: String,
+    pub region: String,
+}
+
+impl TextureAtlas {
+    pub fn new(name: String, texture: String, width: u32, height: u32, regions: Vec<AtlasRegion>) -> Self {
+        let region_indices = regions.iter().enumerate()
+            .map(|(i, region)| (region.name.clone(), i))
+            .collect();
+        Self { name, texture, width, height, regions, region_indices }
+    }
+
+    pub fn load(path: &Path, data_directory: &Path) -> Result<Self, DataError> {
+        let file = File::open(path).map_err(|e| DataError::Io {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+        })?;
+        let packer_file: PackerFile = serde_json::from_reader(file).map_err(|e| DataError::Parse {
+            path: path.to_path_buf(),
+            line: e.line(),
+            column: e.column(),
+            message: e.to_string(),
+        })?;
+
+        // The image is relative to the atlas file.
+        let image_path = path.parent().unwrap_or(Path::new("")).join(&packer_file.meta.image);
+        let texture = image_path.strip_prefix(data_directory)
+            .map(|relative_path| relative_path.to_string_lossy().to_string())
+            .unwrap_or(packer_file.meta.image);
+
+        let frames = match packer_file.frames {
+            PackerFrames::Hash(frames) => frames.into_iter()
+                .map(|(name, frame)| PackerFrame { filename: name, ..frame })
+                .collect(),
+            PackerFrames::Array(frames) => frames,
+        };
+        Ok(Self::new(
+            path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default(),
+            texture,
+            packer_file.meta.size.w,
+            packer_file.meta.size.h,
+            frames.into_iter().map(AtlasRegion::from).collect(),
+        ))
+    }
+
+    // Writes the atlas in the array format. The texture has to be in the same directory as the atlas file.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let image = Path::new(&self.texture).file_name().context("Invalid texture path")?;
+        let packer_file = PackerFile {
+            frames: PackerFrames::Array(self.regions.iter().map(PackerFrame::from).collect()),
+            meta: PackerMeta {
+                image: image.to_string_lossy().to_string(),
+                size: PackerSize { w: self.width, h: self.height },
+            },
+        };
+        let json_string = serde_json::to_string_pretty(&packer_file)?;
+        fs::write(path, json_string)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(())
+    }
+
+    pub fn get_region(&self, name: &str) -> Option<&AtlasRegion> {
+        self.region_indices.get(name).map(|index| &self.regions[*index])
+    }
+
+    // A quad with the size of the region in pixels and its origin at the pivot.
+    pub fn build_sprite_mesh(&self, mesh_name: &str, region: &AtlasRegion) -> MeshData {
+        let left = region.offset.0 as f32 - region.pivot.0 * region.source_size.0 as f32;
+        let right = left + region.width as f32;
+        let top = region.pivot.1 * region.source_size.1 as f32 - region.offset.1 as f32;
+        let bottom = top - region.height as f32;
+
+        // The corners in the atlas in pixels from the top left, starting with the bottom left one of the sprite and going
+        // counterclockwise.
+        let (x, y) = (region.x as f32, region.y as f32);
+        let (width, height) = (region.width as f32, region.height as f32);
+        let corners = if region.rotated {
+            [(x, y), (x, y + width), (x + height, y + width), (x + height, y)]
+        }
+        else {
+            [(x, y + height), (x + width, y + height), (x + width, y), (x, y)]
+        };
+        let positions = [(left, bottom), (right, bottom), (right, top), (left, top)];
+
+        let vertices = positions.iter().zip(corners.iter())
+            .map(|((x, y), (u, v))| Vertex {
+                x: *x,
+                y: *y,
+                u: u / self.width as f32,
+                // Texture coordinates of meshes start at the bottom.
+                v: 1.0 - v / self.height as f32,
+            })
+            .collect();
+        MeshData {
+            mesh_name: mesh_name.to_string(),
+            texture: self.texture.clone(),
+            vertices,
+            lines: Vec::new(),
+            triangles: Vec::new(),
+            quads: vec![Quad { v1: 0, v2: 1, v3: 2, v4: 3 }],
+            is_generated: true,
+        }
+    }
+}
+
+impl From<PackerFrame> for AtlasRegion {
+    fn from(frame: PackerFrame) -> Self {
+        let (offset, source_size) = match (frame.sprite_source_size, frame.source_size) {
+            (Some(sprite_source_size), Some(source_size)) => {
+                ((sprite_source_size.x, sprite_source_size.y), (source_size.w, source_size.h))
+            }
+            _ => ((0, 0), (frame.frame.w, frame.frame.h)),
+        };
+        let pivot = frame.pivot.map_or((0.5, 0.5), |pivot| (pivot.x, pivot.y));
+        Self {
+            name: frame.filename,
+            x: frame.frame.x,
+            y: frame.frame.y,
+            width: frame.frame.w,
+            height: frame.frame.h,
+            rotated: frame.rotated,
+            offset,
+            source_size,
+            pivot,
+        }
+    }
+}
+
+impl From<&AtlasRegion> for PackerFrame {
+    fn from(region: &AtlasRegion) -> Self {
+        Self {
+            filename: region.name.clone(),
+            frame: PackerRect { x: region.x, y: region.y, w: region.width, h: region.height },
+            rotated: region.rotated,
+            sprite_source_size: Some(PackerRect {
+                x: region.offset.0,
+                y: region.offset.1,
+                w: region.width,
+                h: region.height,
+            }),
+            source_size: Some(PackerSize { w: region.source_size.0, h: region.source_size.1 }),
+            pivot: Some(PackerPoint { x: region.pivot.0, y: region.pivot.1 }),
+        }
+    }
+}
+
+// The TexturePacker JSON format. Frames are either a map from names to frames ("JSON (Hash)") or a list of frames with
+// file names ("JSON (Array)").
+#[derive(Serialize, Deserialize)]
+struct PackerFile {
+    frames: PackerFrames,
+    meta: PackerMeta,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum PackerFrames {
+    Hash(BTreeMap<String, PackerFrame>),
+    Array(Vec<PackerFrame>),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PackerFrame {
+    #[serde(default)]
+    filename: String,
+    frame: PackerRect,
+    #[serde(default)]
+    rotated: bool,
+    sprite_source_size: Option<PackerRect>,
+    source_size: Option<PackerSize>,
+    pivot: Option<PackerPoint>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PackerMeta {
+    image: String,
+    size: PackerSize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PackerRect {
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PackerSize {
+    w: u32,
+    h: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PackerPoint {
+    x: f32,
+    y: f32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region(name: &str, x: u32, y: u32, width: u32, height: u32) -> AtlasRegion {
+        AtlasRegion {
+            name: name.to_string(),
+            x,
+            y,
+            width,
+            height,
+            rotated: false,
+            offset: (0, 0),
+            source_size: (width, height),
+            pivot: (0.5, 0.5),
+        }
+    }
+
+    #[test]
+    fn load_hash_format() {
+        let directory = TestDirectory::new("atlas-hash");
+        fs::create_dir_all(directory.path.join("sprites")).unwrap();
+        let path = directory.path.join("sprites").join("characters.json");
+        fs::write(&path, r#"{
+            "frames": {
+                "hero.png": {
+                    "frame": { "x": 2, "y": 4, "w": 10, "h": 20 },
+                    "rotated": false,
+                    "trimmed": true,
+                    "spriteSourceSize": { "x": 3, "y": 1, "w": 10, "h": 20 },
+                    "sourceSize": { "w": 16, "h": 24 },
+                    "pivot": { "x": 0.5, "y": 1.0 }
+                },
+                "enemy.png": {
+                    "frame": { "x": 20, "y": 0, "w": 8, "h": 8 }
+                }
+            },
+            "meta": { "image": "characters.png", "size": { "w": 32, "h": 32 } }
+        }"#).unwrap();
+
+        let atlas = TextureAtlas::load(&path, &directory.path).unwrap();
+        assert_eq!(atlas.name, "characters");
+        assert_eq!(Path::new(&atlas.texture), Path::new("sprites/characters.png"));
+        assert_eq!((atlas.width, atlas.height), (32, 32));
+
+        let hero = atlas.get_region("hero.png").unwrap();
+        assert_eq!((hero.x, hero.y, hero.width, hero.height), (2, 4, 10, 20));
+        assert_eq!(hero.offset, (3, 1));
+        assert_eq!(hero.source_size, (16, 24));
+        assert_eq!(hero.pivot, (0.5, 1.0));
+
+        let enemy = atlas.get_region("enemy.png").unwrap();
+        assert_eq!(enemy.offset, (0, 0));
+        assert_eq!(enemy.source_size, (8, 8));
+        assert_eq!(enemy.pivot, (0.5, 0.5));
+    }
+
+    #[test]
+    fn save_and_load_array_format() {
+        let directory = TestDirectory::new("atlas-array");
+        let path = directory.path.join("page.json");
+        let regions = vec![region("a.png", 0, 0, 16, 8), region("b.png", 18, 0, 4, 4)];
+        TextureAtlas::new("page".to_string(), "page.png".to_string(), 32, 16, regions).save(&path).unwrap();
+
+        let atlas = TextureAtlas::load(&path, &directory.path).unwrap();
+        assert_eq!(atlas.texture, "page.png");
+        assert_eq!(atlas.regions.len(), 2);
+        let b = atlas.get_region("b.png").unwrap();
+        assert_eq!((b.x, b.y, b.width, b.height), (18, 0, 4, 4));
+    }
+
+    #[test]
+    fn load_missing_file() {
+        let directory = TestDirectory::new("atlas-missing");
+        let result = TextureAtlas::load(&directory.path.join("missing.json"), &directory.path);
+        assert!(matches!(result, Err(DataError::Io { .. })));
+    }
+
+    // Texture coordinates of meshes start at the bottom of the texture, while regions start at the top.
+    #[test]
+    fn sprite_texture_coordinates() {
+        let regions = vec![region("top", 0, 0, 16, 8), region("bottom", 16, 24, 16, 8)];
+        let atlas = TextureAtlas::new("atlas".to_string(), "atlas.png".to_string(), 64, 32, regions);
+
+        let mesh = atlas.build_sprite_mesh("top", atlas.get_region("top").unwrap());
+        let texture_coordinates: Vec<(f32, f32)> = mesh.vertices.iter().map(|vertex| (vertex.u, vertex.v)).collect();
+        assert_eq!(texture_coordinates, vec![(0.0, 0.75), (0.25, 0.75), (0.25, 1.0), (0.0, 1.0)]);
+        let positions: Vec<(f32, f32)> = mesh.vertices.iter().map(|vertex| (vertex.x, vertex.y)).collect();
+        assert_eq!(positions, vec![(-8.0, -4.0), (8.0, -4.0), (8.0, 4.0), (-8.0, 4.0)]);
+
+        let mesh = atlas.build_sprite_mesh("bottom", atlas.get_region("bottom").unwrap());
+        let texture_coordinates: Vec<(f32, f32)> = mesh.vertices.iter().map(|vertex| (vertex.u, vertex.v)).collect();
+        assert_eq!(texture_coordinates, vec![(0.25, 0.0), (0.5, 0.0), (0.5, 0.25), (0.25, 0.25)]);
+    }
+
+    #[test]
+    fn rotated_sprite_texture_coordinates() {
+        // Stored turned clockwise, so the region takes up 8x16 pixels in the atlas.
+        let rotated = AtlasRegion { rotated: true, ..region("rotated", 0, 0, 16, 8) };
+        let atlas = TextureAtlas::new("atlas".to_string(), "atlas.png".to_string(), 32, 32, vec![rotated]);
+
+        let mesh = atlas.build_sprite_mesh("rotated", atlas.get_region("rotated").unwrap());
+        let texture_coordinates: Vec<(f32, f32)> = mesh.vertices.iter().map(|vertex| (vertex.u, vertex.v)).collect();
+        assert_eq!(texture_coordinates, vec![(0.0, 1.0), (0.0, 0.5), (0.25, 0.5), (0.25, 1.0)]);
+    }
+
+    #[test]
+    fn trimmed_sprite_keeps_pivot() {
+        let trimmed = AtlasRegion {
+            offset: (4, 2),
+            source_size: (16, 16),
+            pivot: (0.0, 1.0),
+            ..region("trimmed", 0, 0, 8, 8)
+        };
+        let atlas = TextureAtlas::new("atlas".to_string(), "atlas.png".to_string(), 32, 32, vec![trimmed]);
+
+        let mesh = atlas.build_sprite_mesh("trimmed", atlas.get_region("trimmed").unwrap());
+        // The pivot is the bottom left corner of the untrimmed image.
+        assert_eq!((mesh.vertices[0].x, mesh.vertices[0].y), (4.0, 6.0));
+        assert_eq!((mesh.vertices[2].x, mesh.vertices[2].y), (12.0, 14.0));
+    }
+}
@@ -1,17 +1,41 @@
-use serde::{Serialize, Deserialize};
+use serde::{Serialize, Serializer, Deserialize};
+use std::{collections::HashSet, path::PathBuf};
 use crate::static_data::*;
 
 #[derive(Default, Serialize, Deserialize, Debug)]
 #[serde(default)]
 pub struct Collection {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
     pub scene_nodes: Vec<SceneNode>,
+
+    #[serde(skip_serializing_if = "has_no_authored_meshes", serialize_with = "serialize_authored_meshes")]
     pub meshes: Vec<MeshData>,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub sprites: Vec<Sprite>,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
     pub rigid_bodies: Vec<RigidBody>,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
     pub colliders: Vec<Collider>,
 
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub joints: Vec<Joint>,
+
+    #[serde(skip_serializing_if = "PhysicsSettings::is_default")]
+    pub physics_settings: PhysicsSettings,
+
+    // Names of the render layers from bottom to top. The default layer is drawn below all of them.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub render_layers: Vec<String>,
+
     #[serde(skip)]
     pub name: String,
 
+    #[serde(skip)]
+    pub path: PathBuf,
+
     #[serde(skip)]
     pub scene_node_indices: HashMap<String, usize>,
 
@@ -26,8 +50,11 @@ pub struct Collection {
 }
 
 impl Collection {
-    pub fn initialize(&mut self, name: String) {
+    // Returns the problems found in the collection. Scene nodes with a parent that doesn't exist are not added to the
+    // hierarchy.
+    pub fn initialize(&mut self, name: String, path: PathBuf) -> Vec<DataError> {
         self.name = name;
+        self.path = path;
         for (i, scene_node) in self.scene_nodes.iter().enumerate() {
             self.scene_node_indices.insert(scene_node.name.clone(), i);
         }
@@ -40,13 +67,71 @@ impl Collection {
         for (i, collider) in self.colliders.iter().enumerate() {
             self.collider_indices.insert(collider.name.clone(), i);
         }
-        
+
+        self.update_children();
+
+        let mut problems = Vec::new();
+        for scene_node in &self.scene_nodes {
+            if let Some(parent_name) = scene_node.get_parent() {
+                if !self.scene_node_indices.contains_key(parent_name) {
+                    problems.push(DataError::DanglingReference {
+                        collection: self.name.clone(),
+                        node: scene_node.name.clone(),
+                        kind: "Parent",
+                        reference: parent_name.clone(),
+                    });
+                }
+            }
+        }
+        problems
+    }
+
+    // Index of a render layer in draw order, where 0 is the default layer.
+    pub fn get_render_layer_index(&self, name: &str) -> Option<usize> {
+        if name.is_empty() {
+            return Some(0);
+        }
+        self.render_layers.iter().position(|layer| layer == name).map(|index| index + 1)
+    }
+
+    // Whether following the parents of a node leads to the ancestor. A node that is its own descendant is part of a
+    // parent cycle.
+    pub fn is_descendant(&self, node_name: &str, ancestor_name: &str) -> bool {
+        let mut visited_names = HashSet::new();
+        let mut parent_name = self.scene_node_indices.get(node_name)
+            .and_then(|index| self.scene_nodes[*index].get_parent());
+        while let Some(name) = parent_name {
+            if name == ancestor_name {
+                return true;
+            }
+            // A cycle that doesn't contain the ancestor.
+            if !visited_names.insert(name) {
+                return false;
+            }
+            parent_name = self.scene_node_indices.get(name)
+                .and_then(|index| self.scene_nodes[*index].get_parent());
+        }
+        false
+    }
+
+    pub fn update_children(&mut self) {
+        for scene_node in &mut self.scene_nodes {
+            scene_node.children.clear();
+        }
         for i in 0..self.scene_nodes.len() {
             if let Some(parent_name) = self.scene_nodes[i].get_parent() {
-                let parent_index = self.scene_node_indices.get(parent_name).unwrap();
-                self.scene_nodes[*parent_index].children.push(i);
+                if let Some(parent_index) = self.scene_node_indices.get(parent_name) {
+                    self.scene_nodes[*parent_index].children.push(i);
+                }
             }
-            self.scene_nodes[i].components.sort_by(scene::compare_components);
         }
     }
 }
+
+fn has_no_authored_meshes(meshes: &[MeshData]) -> bool {
+    meshes.iter().all(|mesh| mesh.is_generated)
+}
+
+fn serialize_authored_meshes<S: Serializer>(meshes: &[MeshData], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(meshes.iter().filter(|mesh| !mesh.is_generated))
+}
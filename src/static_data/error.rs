@@ -0,0 +1,117 @@
+use std::{
+    fmt,
+    path::PathBuf,
+};
+
+#[derive(Debug, Clone)]
+pub enum DataError {
+    Io {
+        path: PathBuf,
+        message: String,
+    },
+    Parse {
+        path: PathBuf,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    DanglingReference {
+        collection: String,
+        node: String,
+        kind: &'static str,
+        reference: String,
+    },
+    DuplicateName {
+        collection: String,
+        kind: &'static str,
+        name: String,
+    },
+    MissingTexture {
+        collection: String,
+        mesh: String,
+        path: PathBuf,
+    },
+    InvalidMesh {
+        collection: String,
+        mesh: String,
+        message: String,
+    },
+    CyclicPrefab {
+        collection: String,
+        node: String,
+        prefab: String,
+    },
+    CyclicParent {
+        collection: String,
+        node: String,
+    },
+    InvalidComponent {
+        collection: String,
+        node: String,
+        message: String,
+    },
+    InvalidPhysicsSettings {
+        collection: String,
+        message: String,
+    },
+}
+
+impl DataError {
+    // Name of the collection the problem is located in, used to replace the problems of a collection when it's
+    // reloaded.
+    pub fn collection_name(&self) -> String {
+        match self {
+            DataError::Io { path, .. } | DataError::Parse { path, .. } => path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            DataError::DanglingReference { collection, .. }
+            | DataError::DuplicateName { collection, .. }
+            | DataError::MissingTexture { collection, .. }
+            | DataError::InvalidMesh { collection, .. }
+            | DataError::CyclicPrefab { collection, .. }
+            | DataError::CyclicParent { collection, .. }
+            | DataError::InvalidComponent { collection, .. }
+            | DataError::InvalidPhysicsSettings { collection, .. } => collection.clone(),
+        }
+    }
+}
+
+impl fmt::Display for DataError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DataError::Io { path, message } => {
+                write!(f, "{}: {}", path.display(), message)
+            }
+            DataError::Parse { path, line, column, message } => {
+                write!(f, "{}:{}:{}: {}", path.display(), line, column, message)
+            }
+            DataError::DanglingReference { collection, node, kind, reference } => {
+                write!(f, "{}/{}: {} \"{}\" does not exist", collection, node, kind, reference)
+            }
+            DataError::DuplicateName { collection, kind, name } => {
+                write!(f, "{}: {} \"{}\" is defined more than once", collection, kind, name)
+            }
+            DataError::MissingTexture { collection, mesh, path } => {
+                write!(f, "{}/{}: Texture {} does not exist", collection, mesh, path.display())
+            }
+            DataError::InvalidMesh { collection, mesh, message } => {
+                write!(f, "{}/{}: {}", collection, mesh, message)
+            }
+            DataError::CyclicPrefab { collection, node, prefab } => {
+                write!(f, "{}/{}: Prefab \"{}\" contains itself", collection, node, prefab)
+            }
+            DataError::CyclicParent { collection, node } => {
+                write!(f, "{}/{}: Node is its own ancestor", collection, node)
+            }
+            DataError::InvalidComponent { collection, node, message } => {
+                write!(f, "{}/{}: {}", collection, node, message)
+            }
+            DataError::InvalidPhysicsSettings { collection, message } => {
+                write!(f, "{}: Invalid physics settings: {}", collection, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DataError {}
@@ -0,0 +1,117 @@
+use std::path::PathBuf;
+use crate::static_data::*;
+
+impl DataAccessor {
+    // Builds a copy of the collection in which prefab instances are replaced by the scene nodes they would spawn. Nodes
+    // from prefab children are named and parented the same way as the spawned entities.
+    pub fn flatten_collection(&self, index: usize) -> Result<Collection, DataError> {
+        let collection = &self.collections[index];
+        let mut scene_nodes = Vec::new();
+        for (i, node) in collection.scene_nodes.iter().enumerate() {
+            if node.get_parent().is_none() {
+                self.flatten_node_recursive((index, i), index, None, &mut Vec::new(), &mut scene_nodes)?;
+            }
+        }
+
+        let mut flattened_collection = Collection {
+            scene_nodes,
+            // Sprites are replaced by the meshes generated from them.
+            meshes: collection.meshes.iter()
+                .map(|mesh| MeshData { is_generated: false, ..mesh.clone() })
+                .collect(),
+            rigid_bodies: collection.rigid_bodies.clone(),
+            colliders: collection.colliders.clone(),
+            joints: collection.joints.clone(),
+            physics_settings: collection.physics_settings.clone(),
+            render_layers: collection.render_layers.clone(),
+            ..Default::default()
+        };
+        let problems = flattened_collection.initialize(collection.name.clone(), PathBuf::new());
+        match problems.into_iter().next() {
+            Some(problem) => Err(problem),
+            None => Ok(flattened_collection),
+        }
+    }
+
+    fn flatten_node_recursive(
+        &self,
+        node_index: (usize, usize),
+        collection_to_spawn_in: usize,
+        parent_name: Option<String>,
+        ancestors: &mut Vec<(usize, usize)>,
+        scene_nodes: &mut Vec<SceneNode>,
+    ) -> Result<(), DataError> {
+        let node_name = self.get_qualified_node_name(node_index, collection_to_spawn_in);
+        let (mut node, children) = self.build_node(&node_name, collection_to_spawn_in)?;
+        if node_index.0 != collection_to_spawn_in {
+            let parent_name = parent_name.unwrap();
+            node.name = format!("{}{}", parent_name, node.name);
+            node.set_parent(parent_name);
+        }
+        let name = node.name.clone();
+        scene_nodes.push(node);
+
+        ancestors.push(node_index);
+        for (child_index, collection) in children {
+            if ancestors.contains(&(collection, child_index)) {
+                return Err(DataError::CyclicPrefab {
+                    collection: self.collections[collection].name.clone(),
+                    node: name,
+                    prefab: self.collections[collection].scene_nodes[child_index].name.clone(),
+                });
+            }
+            self.flatten_node_recursive((collection, child_index), collection_to_spawn_in, Some(name.clone()), ancestors, scene_nodes)?;
+        }
+        ancestors.pop();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flatten_prefab_instances() {
+        let directory = TestDirectory::new("flatten");
+        let data_accessor = directory.load_collections(&[
+            ("boxes", r#"(
+                scene_nodes: [
+                    (name: "box", components: [Transform(translation: (0.0, 0.0), rotation: 0.0)]),
+                    (name: "lid", components: [Parent("box"), Transform(translation: (0.0, 32.0), rotation: 0.0)]),
+                ],
+            )"#),
+            ("scene", r#"(
+                scene_nodes: [
+                    (name: "crate", prefab: "boxes/box", components: [Transform(translation: (5.0, 6.0), rotation: 0.0)]),
+                ],
+            )"#),
+        ]);
+        let collection = data_accessor.flatten_collection(data_accessor.collection_indices["scene"]).unwrap();
+        let nodes: Vec<_> = collection.scene_nodes.iter()
+            .map(|node| (node.name.as_str(), node.prefab.as_str(), node.get_parent().map(|parent| parent.as_str())))
+            .collect();
+        assert_eq!(nodes, vec![("crate", "", None), ("cratelid", "", Some("crate"))]);
+        assert!(collection.scene_node_indices.contains_key("cratelid"));
+
+        // The instance overrides the transform of the prefab.
+        let translation = collection.scene_nodes[0].components.iter().find_map(|component| match component {
+            Component::Transform { translation, .. } => Some(*translation),
+            _ => None,
+        });
+        assert_eq!(translation, Some((5.0, 6.0)));
+    }
+
+    #[test]
+    fn flatten_cyclic_prefab() {
+        let directory = TestDirectory::new("flatten-cyclic");
+        let data_accessor = directory.load_collections(&[("scene", r#"(
+            scene_nodes: [
+                (name: "a", components: []),
+                (name: "b", prefab: "a", components: [Parent("a")]),
+            ],
+        )"#)]);
+        let result = data_accessor.flatten_collection(0);
+        assert!(matches!(result, Err(DataError::CyclicPrefab { .. })), "{:?}", result.map(|_| ()));
+    }
+}
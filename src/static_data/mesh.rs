@@ -1,19 +1,50 @@
 use serde::{Serialize, Deserialize};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct MeshData {
     pub mesh_name: String,
     pub texture: String,
     pub vertices: Vec<Vertex>,
 
-    #[serde(default)]
-    lines: Vec<Line>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub lines: Vec<Line>,
 
     #[serde(default)]
     pub triangles: Vec<Triangle>,
 
-    #[serde(default)]
-    quads: Vec<Quad>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub quads: Vec<Quad>,
+
+    // Generated from a sprite when the collection was loaded, so it isn't saved.
+    #[serde(skip)]
+    pub is_generated: bool,
+}
+
+impl MeshData {
+    // Indices of the triangles followed by those of the quads, which are split along their v1-v3 diagonal.
+    pub fn get_triangle_indices(&self) -> Vec<u32> {
+        let mut indices: Vec<u32> = bytemuck::cast_slice(&self.triangles).to_vec();
+        for quad in &self.quads {
+            indices.extend_from_slice(&[quad.v1, quad.v2, quad.v3, quad.v3, quad.v4, quad.v1]);
+        }
+        indices
+    }
+
+    pub fn get_line_indices(&self) -> Vec<u32> {
+        self.lines.iter().flat_map(|line| vec![line.v1, line.v2]).collect()
+    }
+
+    // Returns a description of the problem if an index doesn't refer to a vertex.
+    pub fn check(&self) -> Result<(), String> {
+        let num_vertices = self.vertices.len();
+        let invalid_index = self.get_triangle_indices().into_iter()
+            .chain(self.get_line_indices())
+            .find(|index| *index as usize >= num_vertices);
+        match invalid_index {
+            Some(index) => Err(format!("Index {} is out of range for {} vertices", index, num_vertices)),
+            None => Ok(()),
+        }
+    }
 }
 
 #[repr(C)]
@@ -25,24 +56,24 @@ pub struct Vertex {
     pub v: f32,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Line {
-    v1: u32,
-    v2: u32,
+    pub v1: u32,
+    pub v2: u32,
 }
 
 #[repr(C)]
 #[derive(Serialize, Deserialize, Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct Triangle {
-    v1: u32,
-    v2: u32,
-    v3: u32,
+    pub v1: u32,
+    pub v2: u32,
+    pub v3: u32,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Quad {
-    v1: u32,
-    v2: u32,
-    v3: u32,
-    v4: u32,
+    pub v1: u32,
+    pub v2: u32,
+    pub v3: u32,
+    pub v4: u32,
 }
@@ -2,23 +2,44 @@ mod collection;
 mod scene;
 mod mesh;
 mod physics;
+mod error;
+mod validation;
+mod flatten;
+mod atlas;
 
 pub use collection::*;
 pub use scene::*;
 pub use mesh::*;
 pub use physics::*;
+pub use error::*;
+pub use validation::*;
+pub use atlas::*;
 
 use std::{
     collections::HashMap,
     ffi::OsStr,
     fs::{self, File, DirEntry},
-    path::Path,
+    path::{Path, PathBuf},
+};
+use anyhow::{Context, Result};
+use ron::{
+    de::from_reader,
+    error::SpannedError,
+    ser::{to_string_pretty, PrettyConfig},
 };
-use ron::de::from_reader;
 
 pub struct DataAccessor {
     pub collections: Vec<Collection>,
     pub collection_indices: HashMap<String, usize>,
+
+    // Directory the collections were loaded from. Texture paths are relative to it.
+    pub directory: PathBuf,
+
+    pub atlases: Vec<TextureAtlas>,
+    pub atlas_indices: HashMap<String, usize>,
+
+    // Problems found while loading the collections. Reloading a collection replaces its problems.
+    pub problems: Vec<DataError>,
 }
 
 impl DataAccessor {
@@ -26,33 +47,169 @@ impl DataAccessor {
         Self {
             collections: Vec::new(),
             collection_indices: HashMap::new(),
+            directory: PathBuf::new(),
+            atlases: Vec::new(),
+            atlas_indices: HashMap::new(),
+            problems: Vec::new(),
         }
     }
 
     pub fn load_collection(&mut self, path: &Path) {
-        let file = File::open(path).unwrap();
-        let mut collection: Collection = match from_reader(file) {
-            Ok(x) => x,
+        // The problem is recorded, so it doesn't need to be handled here.
+        let _ = self.reload_collection(path);
+    }
+
+    // Replaces the collection loaded from the given path, or adds it if it is new. Returns the collection index. On
+    // failure the previously loaded version is kept.
+    pub fn reload_collection(&mut self, path: &Path) -> Result<usize, DataError> {
+        let name = path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+        self.problems.retain(|problem| problem.collection_name() != name);
+
+        let mut collection = match Self::read_collection(path) {
+            Ok(collection) => collection,
+            Err(e) => {
+                self.problems.push(e.clone());
+                return Err(e);
+            }
+        };
+        let sprite_problems = self.generate_sprite_meshes(&name, &mut collection);
+        self.problems.extend(sprite_problems);
+        let problems = collection.initialize(name.clone(), path.to_path_buf());
+        self.problems.extend(problems);
+        match self.collection_indices.get(&name) {
+            Some(index) => {
+                self.collections[*index] = collection;
+                Ok(*index)
+            }
+            None => {
+                let index = self.collections.len();
+                self.collection_indices.insert(name, index);
+                self.collections.push(collection);
+                Ok(index)
+            }
+        }
+    }
+
+    fn generate_sprite_meshes(&self, collection_name: &str, collection: &mut Collection) -> Vec<DataError> {
+        let mut problems = Vec::new();
+        for sprite in &collection.sprites {
+            let atlas = match self.atlas_indices.get(&sprite.atlas) {
+                Some(index) => &self.atlases[*index],
+                None => {
+                    problems.push(DataError::DanglingReference {
+                        collection: collection_name.to_string(),
+                        node: sprite.mesh_name.clone(),
+                        kind: "Atlas",
+                        reference: sprite.atlas.clone(),
+                    });
+                    continue;
+                }
+            };
+            match atlas.get_region(&sprite.region) {
+                Some(region) => collection.meshes.push(atlas.build_sprite_mesh(&sprite.mesh_name, region)),
+                None => problems.push(DataError::DanglingReference {
+                    collection: collection_name.to_string(),
+                    node: sprite.mesh_name.clone(),
+                    kind: "Atlas region",
+                    reference: format!("{}/{}", sprite.atlas, sprite.region),
+                }),
+            }
+        }
+        problems
+    }
+
+    // Replaces the atlas loaded from the given path, or adds it if it is new. Collections aren't updated, so their
+    // sprites need to be generated again by reloading them.
+    pub fn reload_atlas(&mut self, path: &Path) -> Result<usize, DataError> {
+        self.problems.retain(|problem| match problem {
+            DataError::Io { path: problem_path, .. } | DataError::Parse { path: problem_path, .. } => problem_path != path,
+            _ => true,
+        });
+
+        let atlas = match TextureAtlas::load(path, &self.directory) {
+            Ok(atlas) => atlas,
             Err(e) => {
-                println!("Failed to load ron: {}", e);
-                std::process::exit(1);
+                self.problems.push(e.clone());
+                return Err(e);
             }
         };
-        let name = path.file_stem().unwrap().to_str().unwrap().to_string();
-        collection.initialize(name.clone());
-        self.collection_indices.insert(name, self.collections.len());
-        self.collections.push(collection);
+        match self.atlas_indices.get(&atlas.name) {
+            Some(index) => {
+                self.atlases[*index] = atlas;
+                Ok(*index)
+            }
+            None => {
+                let index = self.atlases.len();
+                self.atlas_indices.insert(atlas.name.clone(), index);
+                self.atlases.push(atlas);
+                Ok(index)
+            }
+        }
+    }
+
+    // Scenes refer to collections by their index, so the collection of a removed file stays loaded. It's reported until
+    // the file is written again, which reloads it. Returns whether a collection was loaded from the path.
+    pub fn flag_removed_collection(&mut self, path: &Path) -> bool {
+        if !self.collections.iter().any(|collection| collection.path == path) {
+            return false;
+        }
+        self.problems.push(DataError::Io {
+            path: path.to_path_buf(),
+            message: "File was removed".to_string(),
+        });
+        true
+    }
+
+    // Removes the atlas with the given name. Like when reloading an atlas, collections aren't updated.
+    pub fn unload_atlas(&mut self, name: &str) -> Option<TextureAtlas> {
+        let index = self.atlas_indices.remove(name)?;
+        let atlas = self.atlases.remove(index);
+        for atlas_index in self.atlas_indices.values_mut() {
+            if *atlas_index > index {
+                *atlas_index -= 1;
+            }
+        }
+        Some(atlas)
+    }
+
+    fn read_collection(path: &Path) -> Result<Collection, DataError> {
+        let file = File::open(path).map_err(|e| DataError::Io {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+        })?;
+        from_reader(file).map_err(|e: SpannedError| DataError::Parse {
+            path: path.to_path_buf(),
+            line: e.span.start.line,
+            column: e.span.start.col,
+            message: e.code.to_string(),
+        })
+    }
+
+    pub fn get_texture_path(&self, texture: &str) -> PathBuf {
+        self.directory.join(texture)
     }
 
     pub fn load_collections_in_directory(&mut self, path: &Path) {
+        self.directory = path.to_path_buf();
         if path.is_dir() {
-            let mut entries = fs::read_dir(path)
-                .unwrap()
-                .into_iter()
-                .collect::<Result<Vec<DirEntry>, _>>()
-                .unwrap();
+            let entries = fs::read_dir(path)
+                .and_then(|entries| entries.collect::<Result<Vec<DirEntry>, _>>());
+            let mut entries = match entries {
+                Ok(entries) => entries,
+                Err(e) => {
+                    self.problems.push(DataError::Io { path: path.to_path_buf(), message: e.to_string() });
+                    return;
+                }
+            };
             entries.sort_by(|a, b| a.path().cmp(&b.path()));
 
+            // Atlases first, since collections generate their sprites from them.
+            for entry in &entries {
+                if entry.path().extension() == Some(OsStr::new("json")) {
+                    // The problem is recorded, so it doesn't need to be handled here.
+                    let _ = self.reload_atlas(&entry.path());
+                }
+            }
             for entry in entries {
                 if let Some(extension) = entry.path().extension() {
                     if extension == OsStr::new("ron") {
@@ -63,6 +220,24 @@ impl DataAccessor {
         }
     }
 
+    pub fn save_collection(&self, index: usize) -> Result<()> {
+        let collection = self.collections.get(index).context("Invalid collection index")?;
+        self.save_collection_as(index, &collection.path)
+    }
+
+    pub fn save_collection_as(&self, index: usize, path: &Path) -> Result<()> {
+        let collection = self.collections.get(index).context("Invalid collection index")?;
+        Self::write_collection(collection, path)
+    }
+
+    pub fn write_collection(collection: &Collection, path: &Path) -> Result<()> {
+        let mut ron_string = to_string_pretty(collection, PrettyConfig::default())?;
+        ron_string.push('\n');
+        fs::write(path, ron_string)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(())
+    }
+
     pub fn get_source_collection_and_component_name(&self, component_path: &String, collection_to_spawn_in: usize) -> Option<(usize, String)> {
         if let Some(index) = component_path.find('/') {
             let (collection_name, component_name) = component_path.split_at(index);
@@ -75,6 +250,19 @@ impl DataAccessor {
         Some((collection_to_spawn_in, component_path.clone()))
     }
 
+    // Name under which a scene node can be found from the collection that is spawned in, i.e. prefixed with the name of
+    // its collection if it comes from a prefab of another one.
+    pub fn get_qualified_node_name(&self, node: (usize, usize), collection_to_spawn_in: usize) -> String {
+        let collection = &self.collections[node.0];
+        let node_name = &collection.scene_nodes[node.1].name;
+        if node.0 == collection_to_spawn_in {
+            node_name.clone()
+        }
+        else {
+            format!("{}/{}", collection.name, node_name)
+        }
+    }
+
     fn get_node(&self, name: &String, collection_to_spawn_in: usize) -> Option<(&SceneNode, usize)> {
         if let Some((source_collection, node_name)) = self.get_source_collection_and_component_name(name, collection_to_spawn_in) {
             let collection = &self.collections[source_collection];
@@ -87,54 +275,84 @@ impl DataAccessor {
         None
     }
 
-    pub fn build_node(&self, name: &String, collection_to_spawn_in: usize) -> Option<(SceneNode, Vec<(usize, usize)>)> {
-        if let Some((mut node, children)) = self.build_prefab_node(name, collection_to_spawn_in, collection_to_spawn_in) {
-            let top_node = self.get_node(name, collection_to_spawn_in).unwrap().0;
-            node.name = top_node.name.clone();
-            if let Some(parent) = top_node.get_parent() {
-                node.set_parent(parent.clone());
-            }
-            node.components.sort_by(scene::compare_components);
-            return Some((node, children));
+    pub fn build_node(&self, name: &String, collection_to_spawn_in: usize) -> Result<(SceneNode, Vec<(usize, usize)>), DataError> {
+        let (top_node, collection) = self.get_node(name, collection_to_spawn_in).ok_or_else(|| DataError::DanglingReference {
+            collection: self.collections[collection_to_spawn_in].name.clone(),
+            node: name.clone(),
+            kind: "Scene node",
+            reference: name.clone(),
+        })?;
+        let mut prefab_chain = Vec::new();
+        let (mut node, children) = self.build_prefab_node(top_node, collection, collection_to_spawn_in, &mut prefab_chain)?;
+        node.name = top_node.name.clone();
+        if let Some(parent) = top_node.get_parent() {
+            node.set_parent(parent.clone());
         }
-        None
+        node.components.sort_by(scene::compare_components);
+        Ok((node, children))
     }
 
-    fn build_prefab_node(&self, name: &String, collection_to_spawn_in: usize, prefab_collection: usize) -> Option<(SceneNode, Vec<(usize, usize)>)> {
-        if let Some((node, collection)) = self.get_node(name, prefab_collection) {
-            let (mut new_node, mut children) = if !node.prefab.is_empty() {
-                self.build_prefab_node(&node.prefab, collection_to_spawn_in, collection).unwrap()
-            }
-            else {
-                (SceneNode::default(), Vec::new())
+    // The prefab chain contains the nodes that are currently being built, to detect prefabs that contain themselves.
+    fn build_prefab_node(
+        &self,
+        node: &SceneNode,
+        collection: usize,
+        collection_to_spawn_in: usize,
+        prefab_chain: &mut Vec<(usize, String)>,
+    ) -> Result<(SceneNode, Vec<(usize, usize)>), DataError> {
+        if prefab_chain.iter().any(|(chain_collection, name)| *chain_collection == collection && *name == node.name) {
+            let (referencing_collection, referencing_node) = prefab_chain.last().unwrap();
+            return Err(DataError::CyclicPrefab {
+                collection: self.collections[*referencing_collection].name.clone(),
+                node: referencing_node.clone(),
+                prefab: node.name.clone(),
+            });
+        }
+        prefab_chain.push((collection, node.name.clone()));
+
+        let (mut new_node, mut children) = if !node.prefab.is_empty() {
+            let (prefab_node, prefab_collection) = self.get_node(&node.prefab, collection).ok_or_else(|| DataError::DanglingReference {
+                collection: self.collections[collection].name.clone(),
+                node: node.name.clone(),
+                kind: "Prefab",
+                reference: node.prefab.clone(),
+            })?;
+            self.build_prefab_node(prefab_node, prefab_collection, collection_to_spawn_in, prefab_chain)?
+        }
+        else {
+            (SceneNode::default(), Vec::new())
+        };
+        new_node.name = node.name.clone();
+        for component in &node.components {
+            let adjust_name = |name: &String| {
+                if collection == collection_to_spawn_in {
+                    name.clone()
+                }
+                else {
+                    let mut prefixed_name = self.collections.get(collection).unwrap().name.clone();
+                    prefixed_name.push('/');
+                    prefixed_name.push_str(&name);
+                    prefixed_name
+                }
             };
-            new_node.name = name.clone();
-            for component in &node.components {
-                let adjust_name = |name: &String| {
-                    if collection == collection_to_spawn_in {
-                        name.clone()
-                    }
-                    else {
-                        let mut prefixed_name = self.collections.get(collection).unwrap().name.clone();
-                        prefixed_name.push('/');
-                        prefixed_name.push_str(&name);
-                        prefixed_name
-                    }
-                };
-                match component {
-                    Component::Transform { translation, rotation } => new_node.set_transform(&translation, *rotation),
-                    Component::Mesh(name) => new_node.set_mesh(adjust_name(name)),
-                    Component::RigidBody(name) => new_node.set_rigid_body(adjust_name(name)),
-                    Component::Collider(name) => new_node.set_collider(adjust_name(name)),
-                    _ => {}
+            match component {
+                Component::Transform { translation, rotation } => new_node.set_transform(&translation, *rotation),
+                Component::Mesh(name) => new_node.set_mesh(adjust_name(name)),
+                Component::RigidBody(name) => new_node.set_rigid_body(adjust_name(name)),
+                Component::Collider(name) => new_node.set_collider(adjust_name(name)),
+                // Layers always refer to the collection that is spawned in.
+                Component::Layer { .. } | Component::Color(..) => {
+                    new_node.replace_component(component, Some(component.clone()))
                 }
+                _ => {}
             }
-            for child in &node.children {
-                children.push((*child, collection));
-            }
-            return Some((new_node, children));
         }
-        None
+        for child in &node.children {
+            children.push((*child, collection));
+        }
+
+        prefab_chain.pop();
+        Ok((new_node, children))
     }
 
     pub fn get_mesh(&self, name: &String, collection_to_spawn_in: usize) -> Option<&MeshData> {
@@ -168,17 +386,73 @@ impl DataAccessor {
     }
 }
 
-/*fn main() -> std::io::Result<()> {
-    let mut file = File::open("data/meshes.ron")?;
-    let mut content = String::new();
-    file.read_to_string(&mut content)?;
-    let deserialized: Scene = serde_json::from_str(&content)?;
-    println!("{:?}", deserialized.meshes[0]);
+// A directory in the system's temporary directory that is only used by one test. It's removed when the test ends,
+// even if it fails.
+#[cfg(test)]
+pub struct TestDirectory {
+    pub path: PathBuf,
+}
+
+#[cfg(test)]
+impl TestDirectory {
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("nox-2d-editor-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        Self { path }
+    }
+
+    // Writes the collections to RON files in the directory and loads them.
+    pub fn load_collections(&self, collections: &[(&str, &str)]) -> DataAccessor {
+        for (name, collection) in collections {
+            fs::write(self.path.join(name).with_extension("ron"), collection).unwrap();
+        }
+        let mut data_accessor = DataAccessor::new();
+        data_accessor.load_collections_in_directory(&self.path);
+        data_accessor
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestDirectory {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removed_collection_is_reported_until_written_again() {
+        let directory = TestDirectory::new("removed-collection");
+        let mut data_accessor = directory.load_collections(&[("scene", "(scene_nodes: [(name: \"a\", components: [])])")]);
+        let path = directory.path.join("scene.ron");
+        fs::remove_file(&path).unwrap();
+        assert!(data_accessor.flag_removed_collection(&path));
+        assert!(!data_accessor.flag_removed_collection(&directory.path.join("other.ron")));
+        assert_eq!(data_accessor.collections.len(), 1);
+        assert_eq!(data_accessor.problems.len(), 1);
 
-    let mut file2 = File::create("data/meshes.ron").unwrap();
-    let pretty = PrettyConfig::default();
-    let ron_string = to_string_pretty(&deserialized, pretty).unwrap();
-    file2.write(ron_string.as_bytes()).unwrap();
+        fs::write(&path, "()").unwrap();
+        data_accessor.reload_collection(&path).unwrap();
+        assert!(data_accessor.problems.is_empty());
+    }
 
-    Ok(())
-}*/
+    #[test]
+    fn unload_atlas() {
+        let directory = TestDirectory::new("unload-atlas");
+        let mut data_accessor = directory.load_collections(&[]);
+        for name in &["a", "b", "c"] {
+            TextureAtlas::new(name.to_string(), format!("{}.png", name), 1, 1, Vec::new())
+                .save(&directory.path.join(name).with_extension("json"))
+                .unwrap();
+            data_accessor.reload_atlas(&directory.path.join(name).with_extension("json")).unwrap();
+        }
+        assert!(data_accessor.unload_atlas("b").is_some());
+        assert!(data_accessor.unload_atlas("b").is_none());
+        assert_eq!(data_accessor.atlases[data_accessor.atlas_indices["c"]].name, "c");
+        assert_eq!(data_accessor.atlases.len(), 2);
+    }
+}
@@ -1,26 +1,463 @@
 use serde::{Serialize, Deserialize};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct RigidBody {
     pub name: String,
     pub status: RigidBodyStatus,
+
+    // Replaces the mass that is computed from the colliders.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mass: Option<f32>,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub linear_damping: f32,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub angular_damping: f32,
+    #[serde(default = "default_one", skip_serializing_if = "is_one")]
+    pub gravity_scale: f32,
+
+    // Initial velocity in pixels per second, and angular velocity in degrees per second.
+    #[serde(default, skip_serializing_if = "is_zero_vector")]
+    pub linear_velocity: (f32, f32),
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub angular_velocity: f32,
+
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub fixed_rotation: bool,
+    // Continuous collision detection isn't supported by the physics engine version yet, so this only causes a warning.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub ccd: bool,
+    #[serde(default = "default_true", skip_serializing_if = "is_true")]
+    pub can_sleep: bool,
+    // Whether the rigid body starts sleeping.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub sleeping: bool,
+}
+
+impl RigidBody {
+    // Returns a description of the problem if the properties can't be used.
+    pub fn check(&self) -> Result<(), String> {
+        if self.mass.is_some_and(|mass| mass <= 0.0) {
+            return Err("Mass needs to be positive".to_string());
+        }
+        if self.linear_damping < 0.0 || self.angular_damping < 0.0 {
+            return Err("Damping can't be negative".to_string());
+        }
+        Ok(())
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum RigidBodyStatus {
     Static,
     Dynamic,
     Kinematic,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Collider {
     pub name: String,
     pub shape: Shape,
+
+    // Position relative to the rigid body in pixels, and rotation in degrees.
+    #[serde(default, skip_serializing_if = "is_zero_vector")]
+    pub offset: (f32, f32),
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub rotation: f32,
+
+    #[serde(default = "default_friction", skip_serializing_if = "is_default_friction")]
+    pub friction: f32,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub restitution: f32,
+    // Sensors have a density of 0 and other colliders one of 1, unless it is set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub density: Option<f32>,
+    // Sensors detect intersections without causing contact forces.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub sensor: bool,
+
+    // Collision groups decide which colliders are checked for contacts. Solver groups decide which of those contacts
+    // cause forces, e.g. for colliders that only react to some others.
+    #[serde(default, skip_serializing_if = "InteractionGroups::is_all")]
+    pub collision_groups: InteractionGroups,
+    #[serde(default, skip_serializing_if = "InteractionGroups::is_all")]
+    pub solver_groups: InteractionGroups,
+}
+
+// Bit masks of 16 groups. Two colliders interact if each one is a member of a group in the filter of the other one.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct InteractionGroups {
+    pub memberships: u16,
+    pub filter: u16,
+}
+
+impl Default for InteractionGroups {
+    fn default() -> Self {
+        Self {
+            memberships: u16::MAX,
+            filter: u16::MAX,
+        }
+    }
+}
+
+impl InteractionGroups {
+    fn is_all(&self) -> bool {
+        *self == Self::default()
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl Collider {
+    // Returns a description of the problem if the collider can't be built.
+    pub fn check(&self) -> Result<(), String> {
+        if self.friction < 0.0 || self.restitution < 0.0 {
+            return Err("Friction and restitution can't be negative".to_string());
+        }
+        if self.density.is_some_and(|density| density < 0.0) {
+            return Err("Density can't be negative".to_string());
+        }
+        self.shape.check()
+    }
+}
+
+// Sizes and points are in pixels.
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Shape {
+    // Half extents.
     Cuboid(f32, f32),
-    Blubb,
+    // Radius.
+    Ball(f32),
+    // Half height of the vertical segment between the centers of the caps, and radius.
+    Capsule(f32, f32),
+    ConvexPolygon(Vec<(f32, f32)>),
+    // Segments between consecutive points. A single segment is a polyline with two points.
+    Polyline(Vec<(f32, f32)>),
+    Compound(Vec<CompoundPart>),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CompoundPart {
+    pub shape: Shape,
+    #[serde(default, skip_serializing_if = "is_zero_vector")]
+    pub offset: (f32, f32),
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub rotation: f32,
+}
+
+impl Shape {
+    // Returns a description of the problem if the shape can't be built.
+    pub fn check(&self) -> Result<(), String> {
+        match self {
+            Shape::Cuboid(hx, hy) => {
+                if *hx <= 0.0 || *hy <= 0.0 {
+                    return Err("Cuboid needs positive half extents".to_string());
+                }
+            }
+            Shape::Ball(radius) => {
+                if *radius <= 0.0 {
+                    return Err("Ball needs a positive radius".to_string());
+                }
+            }
+            Shape::Capsule(half_height, radius) => {
+                if *half_height < 0.0 || *radius <= 0.0 {
+                    return Err("Capsule needs a positive radius and half height".to_string());
+                }
+            }
+            Shape::ConvexPolygon(points) => {
+                if points.len() < 3 {
+                    return Err("ConvexPolygon needs at least 3 points".to_string());
+                }
+                if !is_convex(points) {
+                    return Err("ConvexPolygon is not convex".to_string());
+                }
+            }
+            Shape::Polyline(points) => {
+                if points.len() < 2 {
+                    return Err("Polyline needs at least 2 points".to_string());
+                }
+            }
+            Shape::Compound(parts) => {
+                if parts.is_empty() {
+                    return Err("Compound needs at least one part".to_string());
+                }
+                for part in parts {
+                    part.shape.check()?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Shapes without an area, i.e. polylines, don't add mass to rigid bodies.
+    pub fn has_area(&self) -> bool {
+        match self {
+            Shape::Polyline(_) => false,
+            Shape::Compound(parts) => parts.iter().any(|part| part.shape.has_area()),
+            _ => true,
+        }
+    }
+}
+
+// Connects the rigid bodies of two scene nodes, which can also be children of prefab instances, named like their
+// entities. Anchors and axes are in pixels in the local space of the nodes.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Joint {
+    pub name: String,
+    pub node1: String,
+    pub node2: String,
+    pub kind: JointKind,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum JointKind {
+    // Lets the bodies rotate around the anchors, i.e. a revolute joint.
+    Ball {
+        #[serde(default)]
+        anchor1: (f32, f32),
+        #[serde(default)]
+        anchor2: (f32, f32),
+        // Target angular velocity in degrees per second.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        motor: Option<JointMotor>,
+    },
+    // Keeps the anchors at the same position with the given rotations in degrees.
+    Fixed {
+        #[serde(default)]
+        anchor1: (f32, f32),
+        #[serde(default, skip_serializing_if = "is_zero")]
+        rotation1: f32,
+        #[serde(default)]
+        anchor2: (f32, f32),
+        #[serde(default, skip_serializing_if = "is_zero")]
+        rotation2: f32,
+    },
+    // Lets the bodies slide along an axis, which is the same in the local space of both nodes.
+    Prismatic {
+        #[serde(default)]
+        anchor1: (f32, f32),
+        #[serde(default)]
+        anchor2: (f32, f32),
+        axis: (f32, f32),
+        // Minimum and maximum distance between the anchors along the axis in pixels.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        limits: Option<(f32, f32)>,
+        // Target velocity in pixels per second.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        motor: Option<JointMotor>,
+    },
+}
+
+// Drives the joint towards a target velocity, applying at most the given impulse in each step.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct JointMotor {
+    pub target_velocity: f32,
+    pub max_impulse: f32,
+}
+
+impl Joint {
+    // Returns a description of the problem if the joint can't be built.
+    pub fn check(&self) -> Result<(), String> {
+        if self.node1 == self.node2 {
+            return Err("Joint needs two different nodes".to_string());
+        }
+        if let JointKind::Prismatic { axis, limits, .. } = &self.kind {
+            if *axis == (0.0, 0.0) {
+                return Err("Prismatic joint needs an axis".to_string());
+            }
+            if limits.is_some_and(|(min, max)| min > max) {
+                return Err("Prismatic joint limits need to be in ascending order".to_string());
+            }
+        }
+        let motor = match &self.kind {
+            JointKind::Ball { motor, .. } | JointKind::Prismatic { motor, .. } => motor.as_ref(),
+            JointKind::Fixed { .. } => None,
+        };
+        if motor.is_some_and(|motor| motor.max_impulse < 0.0) {
+            return Err("Joint motor impulse can't be negative".to_string());
+        }
+        Ok(())
+    }
+}
+
+// The points have to turn in the same direction at every corner, in either order.
+fn is_convex(points: &[(f32, f32)]) -> bool {
+    let mut sign = 0.0;
+    for i in 0..points.len() {
+        let (a, b, c) = (points[i], points[(i + 1) % points.len()], points[(i + 2) % points.len()]);
+        let cross = (b.0 - a.0) * (c.1 - b.1) - (b.1 - a.1) * (c.0 - b.0);
+        if cross == 0.0 {
+            continue;
+        }
+        if sign == 0.0 {
+            sign = cross.signum();
+        }
+        else if cross.signum() != sign {
+            return false;
+        }
+    }
+    sign != 0.0
+}
+
+// World settings of the scene of a collection.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct PhysicsSettings {
+    // In meters per second squared.
+    pub gravity: (f32, f32),
+    // Length of a physics step in seconds.
+    pub timestep: f32,
+    // Maximum number of steps per frame. Time beyond that is dropped, so that the simulation slows down instead of
+    // taking ever longer frames.
+    pub max_substeps: u32,
+    // Converts the pixels that everything is authored in to the meters used by the physics engine.
+    pub pixels_per_meter: f32,
+    // Iterations of the velocity constraint solver.
+    pub solver_iterations: usize,
+}
+
+impl PhysicsSettings {
+    pub fn check(&self) -> Result<(), String> {
+        if self.timestep <= 0.0 {
+            return Err("Timestep needs to be positive".to_string());
+        }
+        if self.pixels_per_meter <= 0.0 {
+            return Err("Pixels per meter need to be positive".to_string());
+        }
+        if self.max_substeps == 0 || self.solver_iterations == 0 {
+            return Err("Substeps and solver iterations need to be at least 1".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl Default for PhysicsSettings {
+    fn default() -> Self {
+        Self {
+            gravity: (0.0, -9.81),
+            timestep: 1.0 / 60.0,
+            max_substeps: 8,
+            pixels_per_meter: 64.0,
+            solver_iterations: 4,
+        }
+    }
+}
+
+// The same as rapier's.
+fn default_friction() -> f32 {
+    0.5
+}
+
+fn is_default_friction(value: &f32) -> bool {
+    *value == default_friction()
+}
+
+fn default_one() -> f32 {
+    1.0
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn is_one(value: &f32) -> bool {
+    *value == 1.0
+}
+
+fn is_true(value: &bool) -> bool {
+    *value
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+fn is_zero(value: &f32) -> bool {
+    *value == 0.0
+}
+
+fn is_zero_vector(offset: &(f32, f32)) -> bool {
+    *offset == (0.0, 0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn part(shape: Shape) -> CompoundPart {
+        CompoundPart { shape, offset: (0.0, 0.0), rotation: 0.0 }
+    }
+
+    #[test]
+    fn convex_polygons() {
+        let square = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+        assert!(is_convex(&square));
+
+        let clockwise: Vec<_> = square.iter().rev().copied().collect();
+        assert!(is_convex(&clockwise));
+
+        // Collinear points are skipped.
+        assert!(is_convex(&[(0.0, 0.0), (1.0, 0.0), (2.0, 0.0), (2.0, 2.0), (0.0, 2.0)]));
+
+        let arrow = [(0.0, 0.0), (2.0, 1.0), (0.0, 2.0), (1.0, 1.0)];
+        assert!(!is_convex(&arrow));
+
+        let line = [(0.0, 0.0), (1.0, 1.0), (2.0, 2.0)];
+        assert!(!is_convex(&line));
+    }
+
+    #[test]
+    fn check_shapes() {
+        assert!(Shape::Cuboid(1.0, 2.0).check().is_ok());
+        assert!(Shape::Cuboid(0.0, 2.0).check().is_err());
+        assert!(Shape::Ball(1.0).check().is_ok());
+        assert!(Shape::Ball(-1.0).check().is_err());
+        assert!(Shape::Capsule(0.0, 1.0).check().is_ok());
+        assert!(Shape::Capsule(1.0, 0.0).check().is_err());
+        assert!(Shape::ConvexPolygon(vec![(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)]).check().is_ok());
+        assert!(Shape::ConvexPolygon(vec![(0.0, 0.0), (1.0, 0.0)]).check().is_err());
+        assert!(Shape::ConvexPolygon(vec![(0.0, 0.0), (2.0, 1.0), (0.0, 2.0), (1.0, 1.0)]).check().is_err());
+        assert!(Shape::Polyline(vec![(0.0, 0.0), (1.0, 0.0)]).check().is_ok());
+        assert!(Shape::Polyline(vec![(0.0, 0.0)]).check().is_err());
+        assert!(Shape::Compound(Vec::new()).check().is_err());
+        assert!(Shape::Compound(vec![part(Shape::Ball(1.0)), part(Shape::Cuboid(1.0, 1.0))]).check().is_ok());
+        assert!(Shape::Compound(vec![part(Shape::Ball(1.0)), part(Shape::Ball(0.0))]).check().is_err());
+    }
+
+    #[test]
+    fn shapes_with_area() {
+        assert!(Shape::Cuboid(1.0, 1.0).has_area());
+        assert!(Shape::ConvexPolygon(vec![(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)]).has_area());
+        assert!(!Shape::Polyline(vec![(0.0, 0.0), (1.0, 0.0)]).has_area());
+        assert!(!Shape::Compound(vec![part(Shape::Polyline(vec![(0.0, 0.0), (1.0, 0.0)]))]).has_area());
+        assert!(Shape::Compound(vec![part(Shape::Polyline(vec![(0.0, 0.0), (1.0, 0.0)])), part(Shape::Ball(1.0))]).has_area());
+    }
+
+    #[test]
+    fn check_physics_settings() {
+        let settings = PhysicsSettings::default();
+        assert!(settings.check().is_ok());
+        assert!(settings.is_default());
+
+        let invalid_settings = [
+            PhysicsSettings { timestep: 0.0, ..PhysicsSettings::default() },
+            PhysicsSettings { pixels_per_meter: -1.0, ..PhysicsSettings::default() },
+            PhysicsSettings { max_substeps: 0, ..PhysicsSettings::default() },
+            PhysicsSettings { solver_iterations: 0, ..PhysicsSettings::default() },
+        ];
+        for settings in &invalid_settings {
+            assert!(settings.check().is_err());
+            assert!(!settings.is_default());
+        }
+    }
+
+    #[test]
+    fn parse_physics_settings_with_defaults() {
+        let settings: PhysicsSettings = ron::de::from_str("(gravity: (0.0, 0.0), pixels_per_meter: 32.0)").unwrap();
+        assert_eq!(settings.gravity, (0.0, 0.0));
+        assert_eq!(settings.pixels_per_meter, 32.0);
+        assert_eq!(settings.timestep, PhysicsSettings::default().timestep);
+        assert_eq!(settings.max_substeps, 8);
+    }
 }
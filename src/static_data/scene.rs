@@ -1,4 +1,7 @@
-use std::cmp::Ordering;
+use std::{
+    cmp::Ordering,
+    mem::discriminant,
+};
 use serde::{Serialize, Deserialize};
 use smallvec::SmallVec;
 
@@ -6,7 +9,7 @@ use smallvec::SmallVec;
 pub struct SceneNode {
     pub name: String,
 
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub prefab: String,
 
     pub components: Vec<Component>,
@@ -16,6 +19,20 @@ pub struct SceneNode {
 }
 
 impl SceneNode {
+    pub fn get_component(&self, kind: &Component) -> Option<&Component> {
+        self.components.iter().find(|component| discriminant(*component) == discriminant(kind))
+    }
+
+    pub fn replace_component(&mut self, kind: &Component, new_component: Option<Component>) {
+        let index = self.components.iter().position(|component| discriminant(component) == discriminant(kind));
+        match (index, new_component) {
+            (Some(index), Some(new_component)) => self.components[index] = new_component,
+            (Some(index), None) => { self.components.remove(index); }
+            (None, Some(new_component)) => self.components.push(new_component),
+            (None, None) => {}
+        }
+    }
+
     pub fn get_parent(&self) -> Option<&String> {
         for component in &self.components {
             if let Component::Parent(name) = component {
@@ -124,6 +141,10 @@ pub enum Component {
     Mesh(String),
     RigidBody(String),
     Collider(String),
+    // Meshes are drawn by layer, and by order within a layer. An empty name means the default layer.
+    Layer { name: String, order: i32 },
+    // RGBA factors the mesh texture is multiplied with.
+    Color(f32, f32, f32, f32),
 }
 
 pub fn compare_components(component_1: &Component, component_2: &Component) -> Ordering {
@@ -134,6 +155,8 @@ pub fn compare_components(component_1: &Component, component_2: &Component) -> O
             Component::Mesh(_) => 2,
             Component::RigidBody(_) => 3,
             Component::Collider(_) => 4,
+            Component::Layer { .. } => 5,
+            Component::Color(..) => 6,
         }
     };
     let priority_1 = get_priority(component_1);
@@ -0,0 +1,417 @@
+use std::collections::HashSet;
+use crate::static_data::*;
+
+// Result of checking all loaded collections. References are resolved the same way as when a scene is spawned.
+#[derive(Default, Debug, Clone)]
+pub struct ValidationReport {
+    pub problems: Vec<DataError>,
+}
+
+impl ValidationReport {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl DataAccessor {
+    pub fn validate(&self) -> ValidationReport {
+        let mut report = ValidationReport::default();
+        for (collection_index, collection) in self.collections.iter().enumerate() {
+            self.validate_names(collection, &mut report);
+            if let Err(message) = collection.physics_settings.check() {
+                report.problems.push(DataError::InvalidPhysicsSettings {
+                    collection: collection.name.clone(),
+                    message,
+                });
+            }
+            for (node_index, node) in collection.scene_nodes.iter().enumerate() {
+                // Components are checked after merging them with those of the prefab, like when the node is spawned.
+                // Nodes whose prefab can't be resolved are reported below, so only their own components are checked.
+                let node_name = self.get_qualified_node_name((collection_index, node_index), collection_index);
+                let resolved_node = self.build_node(&node_name, collection_index).ok().map(|(resolved_node, _)| resolved_node);
+                let resolved_node = resolved_node.as_ref().unwrap_or(node);
+                self.validate_references(collection_index, node, resolved_node, &mut report);
+                self.validate_mass(collection_index, resolved_node, &mut report);
+            }
+            // Nodes in a parent cycle are neither roots nor children of one, so they would never be spawned.
+            for node in &collection.scene_nodes {
+                if collection.is_descendant(&node.name, &node.name) {
+                    report.problems.push(DataError::CyclicParent {
+                        collection: collection.name.clone(),
+                        node: node.name.clone(),
+                    });
+                }
+            }
+            for node in &collection.scene_nodes {
+                if !node.prefab.is_empty() {
+                    self.validate_prefab_chain(collection_index, node, &mut report);
+                }
+            }
+            for mesh in &collection.meshes {
+                let path = self.get_texture_path(&mesh.texture);
+                if !path.is_file() {
+                    report.problems.push(DataError::MissingTexture {
+                        collection: collection.name.clone(),
+                        mesh: mesh.mesh_name.clone(),
+                        path,
+                    });
+                }
+                if let Err(message) = mesh.check() {
+                    report.problems.push(DataError::InvalidMesh {
+                        collection: collection.name.clone(),
+                        mesh: mesh.mesh_name.clone(),
+                        message,
+                    });
+                }
+            }
+            for rigid_body in &collection.rigid_bodies {
+                if let Err(message) = rigid_body.check() {
+                    report.problems.push(DataError::InvalidComponent {
+                        collection: collection.name.clone(),
+                        node: rigid_body.name.clone(),
+                        message,
+                    });
+                }
+            }
+            for collider in &collection.colliders {
+                if let Err(message) = collider.check() {
+                    report.problems.push(DataError::InvalidComponent {
+                        collection: collection.name.clone(),
+                        node: collider.name.clone(),
+                        message,
+                    });
+                }
+            }
+            // Joints can connect children of prefab instances, so their nodes are looked up by the names of the spawned
+            // entities. Collections that can't be flattened have other problems, so only their own nodes are used then.
+            let flattened_collection = if collection.joints.is_empty() {
+                None
+            }
+            else {
+                self.flatten_collection(collection_index).ok()
+            };
+            let node_indices = &flattened_collection.as_ref().unwrap_or(collection).scene_node_indices;
+            for joint in &collection.joints {
+                if let Err(message) = joint.check() {
+                    report.problems.push(DataError::InvalidComponent {
+                        collection: collection.name.clone(),
+                        node: joint.name.clone(),
+                        message,
+                    });
+                }
+                for node_name in [&joint.node1, &joint.node2].iter() {
+                    if !node_indices.contains_key(*node_name) {
+                        report.problems.push(DataError::DanglingReference {
+                            collection: collection.name.clone(),
+                            node: joint.name.clone(),
+                            kind: "Joint node",
+                            reference: (*node_name).clone(),
+                        });
+                    }
+                }
+            }
+        }
+        report
+    }
+
+    fn validate_names(&self, collection: &Collection, report: &mut ValidationReport) {
+        let mut check_duplicates = |kind: &'static str, names: Vec<&String>| {
+            let mut seen_names = HashSet::new();
+            let mut reported_names = HashSet::new();
+            for name in names {
+                if !seen_names.insert(name) && reported_names.insert(name) {
+                    report.problems.push(DataError::DuplicateName {
+                        collection: collection.name.clone(),
+                        kind,
+                        name: name.clone(),
+                    });
+                }
+            }
+        };
+        check_duplicates("Scene node", collection.scene_nodes.iter().map(|node| &node.name).collect());
+        check_duplicates("Mesh", collection.meshes.iter().map(|mesh| &mesh.mesh_name).collect());
+        check_duplicates("RigidBody", collection.rigid_bodies.iter().map(|rigid_body| &rigid_body.name).collect());
+        check_duplicates("Collider", collection.colliders.iter().map(|collider| &collider.name).collect());
+        check_duplicates("Joint", collection.joints.iter().map(|joint| &joint.name).collect());
+    }
+
+    // The prefab is taken from the node itself, the other references from the node merged with its prefab. References
+    // of components that come from a prefab in another collection are qualified with its name, except for layers.
+    fn validate_references(&self, collection_index: usize, node: &SceneNode, resolved_node: &SceneNode, report: &mut ValidationReport) {
+        let collection = &self.collections[collection_index];
+        let mut check = |kind: &'static str, reference: &String, resolves: bool| {
+            if !resolves {
+                report.problems.push(DataError::DanglingReference {
+                    collection: collection.name.clone(),
+                    node: node.name.clone(),
+                    kind,
+                    reference: reference.clone(),
+                });
+            }
+        };
+
+        if !node.prefab.is_empty() {
+            check("Prefab", &node.prefab, self.get_node(&node.prefab, collection_index).is_some());
+        }
+        for component in &resolved_node.components {
+            match component {
+                // Parents can't be in other collections.
+                Component::Parent(name) => check("Parent", name, collection.scene_node_indices.contains_key(name)),
+                Component::Mesh(name) => check("Mesh", name, self.get_mesh(name, collection_index).is_some()),
+                Component::RigidBody(name) => check("RigidBody", name, self.get_rigid_body(name, collection_index).is_some()),
+                Component::Collider(name) => check("Collider", name, self.get_collider(name, collection_index).is_some()),
+                Component::Layer { name, .. } => check("Layer", name, collection.get_render_layer_index(name).is_some()),
+                _ => {}
+            }
+        }
+    }
+
+    // A dynamic rigid body without mass can't be simulated. Its mass comes from the collider, unless it's set.
+    fn validate_mass(&self, collection_index: usize, node: &SceneNode, report: &mut ValidationReport) {
+        let rigid_body = node.components.iter().find_map(|component| match component {
+            Component::RigidBody(name) => self.get_rigid_body(name, collection_index),
+            _ => None,
+        });
+        let collider = node.components.iter().find_map(|component| match component {
+            Component::Collider(name) => self.get_collider(name, collection_index),
+            _ => None,
+        });
+        if let Some(rigid_body) = rigid_body {
+            let is_dynamic = matches!(rigid_body.status, RigidBodyStatus::Dynamic);
+            let has_area = matches!(collider, Some(collider) if collider.shape.has_area());
+            if is_dynamic && rigid_body.mass.is_none() && !has_area {
+                report.problems.push(DataError::InvalidComponent {
+                    collection: self.collections[collection_index].name.clone(),
+                    node: node.name.clone(),
+                    message: format!("Dynamic RigidBody {} needs a mass or a collider with an area", rigid_body.name),
+                });
+            }
+        }
+    }
+
+    // Follows the prefabs of a node. Reaching the node itself or one of its ancestors means spawning it would never
+    // end.
+    fn validate_prefab_chain(&self, collection_index: usize, node: &SceneNode, report: &mut ValidationReport) {
+        let collection = &self.collections[collection_index];
+
+        let mut forbidden_nodes = vec![(collection_index, node.name.clone())];
+        let mut parent_name = node.get_parent();
+        while let Some(name) = parent_name {
+            if forbidden_nodes.iter().any(|(_, forbidden_name)| forbidden_name == name) {
+                break;
+            }
+            forbidden_nodes.push((collection_index, name.clone()));
+            parent_name = collection.scene_node_indices.get(name)
+                .and_then(|index| collection.scene_nodes[*index].get_parent());
+        }
+
+        let mut visited_nodes = HashSet::new();
+        let mut prefab = (&node.prefab, collection_index);
+        while !prefab.0.is_empty() {
+            let (prefab_node, prefab_collection) = match self.get_node(prefab.0, prefab.1) {
+                Some(resolved) => resolved,
+                None => return,
+            };
+            let key = (prefab_collection, prefab_node.name.clone());
+            if forbidden_nodes.contains(&key) {
+                report.problems.push(DataError::CyclicPrefab {
+                    collection: collection.name.clone(),
+                    node: node.name.clone(),
+                    prefab: node.prefab.clone(),
+                });
+                return;
+            }
+            // Cycles that don't contain this node are reported for the nodes that are part of them.
+            if !visited_nodes.insert(key) {
+                return;
+            }
+            prefab = (&prefab_node.prefab, prefab_collection);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    // Meshes use a texture that has to exist.
+    fn load(directory: &TestDirectory, collections: &[(&str, &str)]) -> DataAccessor {
+        fs::write(directory.path.join("box.png"), []).unwrap();
+        directory.load_collections(collections)
+    }
+
+    fn problems(data_accessor: &DataAccessor) -> Vec<String> {
+        data_accessor.validate().problems.iter().map(|problem| problem.to_string()).collect()
+    }
+
+    const BOXES: &str = r#"(
+        scene_nodes: [
+            (name: "box", components: [Transform(translation: (0.0, 0.0), rotation: 0.0), Mesh("box"),
+                RigidBody("dynamic"), Collider("square")]),
+            (name: "lid", components: [Parent("box"), Transform(translation: (0.0, 32.0), rotation: 0.0)]),
+        ],
+        meshes: [(
+            mesh_name: "box",
+            texture: "box.png",
+            vertices: [(x: 0.0, y: 0.0, u: 0.0, v: 0.0), (x: 1.0, y: 0.0, u: 1.0, v: 0.0), (x: 0.0, y: 1.0, u: 0.0, v: 1.0)],
+            triangles: [(v1: 0, v2: 1, v3: 2)],
+        )],
+        rigid_bodies: [(name: "dynamic", status: Dynamic)],
+        colliders: [(name: "square", shape: ConvexPolygon([(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)]))],
+    )"#;
+
+    #[test]
+    fn valid_collections() {
+        let directory = TestDirectory::new("validate-valid");
+        let data_accessor = load(&directory, &[("boxes", BOXES), ("scene", r#"(
+            scene_nodes: [
+                (name: "crate", prefab: "boxes/box", components: []),
+                (name: "anchor", components: [Transform(translation: (0.0, 0.0), rotation: 0.0),
+                    RigidBody("boxes/dynamic"), Collider("boxes/square")]),
+            ],
+            joints: [(name: "hinge", node1: "anchor", node2: "cratelid", kind: Ball())],
+        )"#)]);
+        assert!(data_accessor.problems.is_empty());
+        assert_eq!(problems(&data_accessor), Vec::<String>::new());
+    }
+
+    #[test]
+    fn duplicate_names() {
+        let directory = TestDirectory::new("validate-duplicates");
+        let data_accessor = load(&directory, &[("scene", r#"(
+            scene_nodes: [(name: "a", components: []), (name: "a", components: []), (name: "a", components: [])],
+            colliders: [(name: "c", shape: Ball(1.0)), (name: "c", shape: Ball(2.0))],
+        )"#)]);
+        assert_eq!(problems(&data_accessor), vec![
+            "scene: Scene node \"a\" is defined more than once",
+            "scene: Collider \"c\" is defined more than once",
+        ]);
+    }
+
+    #[test]
+    fn dangling_references() {
+        let directory = TestDirectory::new("validate-dangling");
+        let data_accessor = load(&directory, &[("boxes", BOXES), ("scene", r#"(
+            scene_nodes: [
+                (name: "a", prefab: "boxes/missing", components: []),
+                (name: "b", components: [Parent("missing"), Mesh("boxes/missing"), Collider("missing")]),
+            ],
+            joints: [(name: "j", node1: "a", node2: "lid", kind: Fixed())],
+        )"#)]);
+        assert_eq!(problems(&data_accessor), vec![
+            "scene/a: Prefab \"boxes/missing\" does not exist",
+            "scene/b: Parent \"missing\" does not exist",
+            "scene/b: Mesh \"boxes/missing\" does not exist",
+            "scene/b: Collider \"missing\" does not exist",
+            "scene/j: Joint node \"lid\" does not exist",
+        ]);
+    }
+
+    #[test]
+    fn cyclic_prefabs() {
+        let directory = TestDirectory::new("validate-prefabs");
+        let data_accessor = load(&directory, &[("scene", r#"(
+            scene_nodes: [
+                (name: "a", prefab: "b", components: []),
+                (name: "b", prefab: "a", components: []),
+                (name: "c", prefab: "a", components: []),
+                (name: "d", components: []),
+                (name: "e", prefab: "d", components: [Parent("d")]),
+            ],
+        )"#)]);
+        assert_eq!(problems(&data_accessor), vec![
+            "scene/a: Prefab \"b\" contains itself",
+            "scene/b: Prefab \"a\" contains itself",
+            "scene/e: Prefab \"d\" contains itself",
+        ]);
+    }
+
+    #[test]
+    fn cyclic_parents() {
+        let directory = TestDirectory::new("validate-parents");
+        let data_accessor = load(&directory, &[("scene", r#"(
+            scene_nodes: [
+                (name: "a", components: [Parent("b")]),
+                (name: "b", components: [Parent("a")]),
+                (name: "c", components: [Parent("a")]),
+                (name: "d", components: [Parent("d")]),
+            ],
+        )"#)]);
+        assert_eq!(problems(&data_accessor), vec![
+            "scene/a: Node is its own ancestor",
+            "scene/b: Node is its own ancestor",
+            "scene/d: Node is its own ancestor",
+        ]);
+    }
+
+    #[test]
+    fn invalid_mesh_indices() {
+        let directory = TestDirectory::new("validate-mesh");
+        let data_accessor = load(&directory, &[("scene", r#"(
+            meshes: [(
+                mesh_name: "broken",
+                texture: "box.png",
+                vertices: [(x: 0.0, y: 0.0, u: 0.0, v: 0.0), (x: 1.0, y: 0.0, u: 1.0, v: 0.0)],
+                triangles: [(v1: 0, v2: 1, v3: 2)],
+            )],
+        )"#)]);
+        let problems = data_accessor.validate().problems;
+        assert_eq!(problems.len(), 1);
+        assert!(matches!(&problems[0], DataError::InvalidMesh { mesh, .. } if mesh == "broken"));
+    }
+
+    #[test]
+    fn dynamic_rigid_bodies_need_mass() {
+        let directory = TestDirectory::new("validate-mass");
+        let data_accessor = load(&directory, &[("scene", r#"(
+            scene_nodes: [
+                (name: "line", components: [RigidBody("dynamic"), Collider("line")]),
+                (name: "heavy_line", components: [RigidBody("heavy"), Collider("line")]),
+                (name: "static_line", components: [RigidBody("static"), Collider("line")]),
+                (name: "ball", components: [RigidBody("dynamic"), Collider("ball")]),
+            ],
+            rigid_bodies: [
+                (name: "dynamic", status: Dynamic),
+                (name: "heavy", status: Dynamic, mass: Some(1.0)),
+                (name: "static", status: Static),
+            ],
+            colliders: [(name: "line", shape: Polyline([(0.0, 0.0), (1.0, 0.0)])), (name: "ball", shape: Ball(1.0))],
+        )"#)]);
+        assert_eq!(problems(&data_accessor), vec![
+            "scene/line: Dynamic RigidBody dynamic needs a mass or a collider with an area",
+        ]);
+    }
+
+    #[test]
+    fn prefab_components() {
+        let directory = TestDirectory::new("validate-prefab-components");
+        let data_accessor = load(&directory, &[
+            ("boxes", r#"(
+                scene_nodes: [
+                    (name: "box_static", components: [RigidBody("rb_box_static"), Collider("c_box")]),
+                    (name: "layered_box", components: [Layer(name: "front", order: 0)]),
+                ],
+                rigid_bodies: [(name: "rb_box_static", status: Static), (name: "rb_box_dynamic", status: Dynamic)],
+                colliders: [(name: "c_box", shape: Cuboid(32.0, 32.0))],
+                render_layers: ["front"],
+            )"#),
+            ("scene", r#"(
+                scene_nodes: [
+                    // The collider of the prefab gives the overriding dynamic rigid body its mass.
+                    (name: "heavy_box", prefab: "boxes/box_static", components: [RigidBody("boxes/rb_box_dynamic")]),
+                    // Layers of prefabs refer to the collection that is spawned in.
+                    (name: "layered_box", prefab: "boxes/layered_box", components: []),
+                ],
+            )"#),
+        ]);
+        assert_eq!(problems(&data_accessor), vec!["scene/layered_box: Layer \"front\" does not exist"]);
+    }
+
+    #[test]
+    fn invalid_physics_settings() {
+        let directory = TestDirectory::new("validate-settings");
+        let data_accessor = load(&directory, &[("scene", "(physics_settings: (timestep: 0.0))")]);
+        assert_eq!(problems(&data_accessor), vec!["scene: Invalid physics settings: Timestep needs to be positive"]);
+    }
+}
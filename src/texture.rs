@@ -1,10 +1,101 @@
 use anyhow::*;
-use image::GenericImageView;
-use std::path::Path;
+use image::{GenericImageView, ImageBuffer, Rgba, RgbaImage};
+use serde::{Serialize, Deserialize};
+use std::fs::File;
+use std::path::{Path, PathBuf};
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
 use crate::asset::{Handle, Assets};
 
+pub const SETTINGS_EXTENSION: &str = "import";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    Nearest,
+    Linear,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Wrap {
+    Clamp,
+    Repeat,
+    MirrorRepeat,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum ColorSpace {
+    Srgb,
+    Linear,
+}
+
+// Import settings of a texture, read from a file next to the image with `.import` appended to its name, e.g.
+// `box.png.import`. Images without one use the defaults.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct TextureSettings {
+    pub mag_filter: Filter,
+    pub min_filter: Filter,
+    pub wrap: Wrap,
+    pub mipmaps: bool,
+
+    // Whether the colors of the image are already multiplied by alpha. Other images are premultiplied when loading,
+    // since the mesh pipeline blends premultiplied colors.
+    pub premultiplied_alpha: bool,
+    pub color_space: ColorSpace,
+}
+
+impl Default for TextureSettings {
+    fn default() -> Self {
+        Self {
+            mag_filter: Filter::Linear,
+            min_filter: Filter::Nearest,
+            wrap: Wrap::Clamp,
+            mipmaps: false,
+            premultiplied_alpha: false,
+            color_space: ColorSpace::Srgb,
+        }
+    }
+}
+
+impl TextureSettings {
+    pub fn get_path<P: AsRef<Path>>(image_path: P) -> PathBuf {
+        let mut path = image_path.as_ref().as_os_str().to_owned();
+        path.push(".");
+        path.push(SETTINGS_EXTENSION);
+        PathBuf::from(path)
+    }
+
+    pub fn load_for_image<P: AsRef<Path>>(image_path: P) -> Result<Self> {
+        let path = Self::get_path(image_path);
+        if !path.is_file() {
+            return Ok(Self::default());
+        }
+        let file = File::open(&path)?;
+        let settings = ron::de::from_reader(file)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Ok(settings)
+    }
+}
+
+impl From<Filter> for wgpu::FilterMode {
+    fn from(filter: Filter) -> Self {
+        match filter {
+            Filter::Nearest => wgpu::FilterMode::Nearest,
+            Filter::Linear => wgpu::FilterMode::Linear,
+        }
+    }
+}
+
+impl From<Wrap> for wgpu::AddressMode {
+    fn from(wrap: Wrap) -> Self {
+        match wrap {
+            Wrap::Clamp => wgpu::AddressMode::ClampToEdge,
+            Wrap::Repeat => wgpu::AddressMode::Repeat,
+            Wrap::MirrorRepeat => wgpu::AddressMode::MirrorRepeat,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Texture {
     pub texture: wgpu::Texture,
@@ -19,13 +110,7 @@ impl Texture {
         queue: &wgpu::Queue,
         path: P,
     ) -> Result<Handle<Texture>> {
-        let id = {
-            let path_copy = path.as_ref().to_path_buf();
-            let path_str = path_copy.to_str();
-            let mut hasher = DefaultHasher::new();
-            path_str.hash(&mut hasher);
-            hasher.finish()
-        };
+        let id = Self::get_id(&path);
         
         if let Some(handle) = textures.get_handle(id) {
             return Ok(handle);
@@ -34,6 +119,30 @@ impl Texture {
         Ok(textures.add(texture, id))
     }
 
+    // Reloads the texture if it is currently in use. Returns whether it was.
+    pub fn reload<P: AsRef<Path>>(
+        textures: &mut Assets<Texture>,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        path: P,
+    ) -> Result<bool> {
+        let id = Self::get_id(&path);
+        if textures.get_handle(id).is_none() {
+            return Ok(false);
+        }
+        let texture = Texture::load(device, queue, path)?;
+        textures.add(texture, id);
+        Ok(true)
+    }
+
+    fn get_id<P: AsRef<Path>>(path: P) -> u64 {
+        let path_copy = path.as_ref().to_path_buf();
+        let path_str = path_copy.to_str();
+        let mut hasher = DefaultHasher::new();
+        path_str.hash(&mut hasher);
+        hasher.finish()
+    }
+
     pub fn load<P: AsRef<Path>>(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
@@ -42,8 +151,9 @@ impl Texture {
         let path_copy = path.as_ref().to_path_buf();
         let label = path_copy.to_str();
 
+        let settings = TextureSettings::load_for_image(&path)?;
         let img = image::open(path)?;
-        Self::from_image(device, queue, &img, label)
+        Self::from_image(device, queue, &img, &settings, label)
     }
 
     /*pub fn from_bytes(
@@ -53,56 +163,91 @@ impl Texture {
         label: &str,
     ) -> Result<Self> {
         let img = image::load_from_memory(bytes)?;
-        Self::from_image(device, queue, &img, Some(label))
+        Self::from_image(device, queue, &img, &TextureSettings::default(), Some(label))
     }*/
 
     pub fn from_image(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         img: &image::DynamicImage,
+        settings: &TextureSettings,
         label: Option<&str>,
     ) -> Result<Self> {
-        let rgba = img.to_rgba8();
+        let mut rgba = img.to_rgba8();
+        if !settings.premultiplied_alpha {
+            premultiply_alpha(&mut rgba, settings.color_space);
+        }
         let dimensions = img.dimensions();
+        let mip_level_count = if settings.mipmaps {
+            32 - dimensions.0.max(dimensions.1).leading_zeros()
+        }
+        else {
+            1
+        };
 
         let size = wgpu::Extent3d {
             width: dimensions.0,
             height: dimensions.1,
             depth: 1,
         };
+        let format = match settings.color_space {
+            ColorSpace::Srgb => wgpu::TextureFormat::Rgba8UnormSrgb,
+            ColorSpace::Linear => wgpu::TextureFormat::Rgba8Unorm,
+        };
         let texture = device.create_texture(&wgpu::TextureDescriptor {
             label,
             size,
-            mip_level_count: 1,
+            mip_level_count,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            format,
             usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
         });
 
-        queue.write_texture(
-            wgpu::TextureCopyView {
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-            },
-            &rgba,
-            wgpu::TextureDataLayout {
-                offset: 0,
-                bytes_per_row: 4 * dimensions.0,
-                rows_per_image: dimensions.1,
-            },
-            size,
-        );
+        // Each mip level is half the size of the previous one and is generated from it. Colors are averaged in linear
+        // space, otherwise sRGB textures get darker in the smaller levels.
+        let mut linear_image = to_linear(&rgba, settings.color_space);
+        let mut level_image = rgba;
+        for mip_level in 0..mip_level_count {
+            if mip_level > 0 {
+                let (width, height) = linear_image.dimensions();
+                linear_image = image::imageops::resize(
+                    &linear_image,
+                    (width / 2).max(1),
+                    (height / 2).max(1),
+                    image::imageops::FilterType::Triangle,
+                );
+                level_image = from_linear(&linear_image, settings.color_space);
+            }
+            let (width, height) = level_image.dimensions();
+            queue.write_texture(
+                wgpu::TextureCopyView {
+                    texture: &texture,
+                    mip_level,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                &level_image,
+                wgpu::TextureDataLayout {
+                    offset: 0,
+                    bytes_per_row: 4 * width,
+                    rows_per_image: height,
+                },
+                wgpu::Extent3d {
+                    width,
+                    height,
+                    depth: 1,
+                },
+            );
+        }
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
+            address_mode_u: settings.wrap.into(),
+            address_mode_v: settings.wrap.into(),
+            address_mode_w: settings.wrap.into(),
+            mag_filter: settings.mag_filter.into(),
+            min_filter: settings.min_filter.into(),
+            mipmap_filter: if settings.mipmaps { wgpu::FilterMode::Linear } else { wgpu::FilterMode::Nearest },
             ..Default::default()
         });
 
@@ -113,3 +258,92 @@ impl Texture {
         })
     }
 }
+
+// sRGB colors are multiplied in linear space, so that they are blended the same way as straight colors.
+fn premultiply_alpha(image: &mut RgbaImage, color_space: ColorSpace) {
+    for pixel in image.pixels_mut() {
+        let alpha = pixel[3] as f32 / 255.0;
+        for channel in &mut pixel.0[..3] {
+            let value = *channel as f32 / 255.0;
+            let value = match color_space {
+                ColorSpace::Srgb => linear_to_srgb(srgb_to_linear(value) * alpha),
+                ColorSpace::Linear => value * alpha,
+            };
+            *channel = (value * 255.0).round() as u8;
+        }
+    }
+}
+
+type LinearImage = ImageBuffer<Rgba<f32>, Vec<f32>>;
+
+// Alpha is always linear.
+fn to_linear(image: &RgbaImage, color_space: ColorSpace) -> LinearImage {
+    ImageBuffer::from_fn(image.width(), image.height(), |x, y| {
+        let pixel = image.get_pixel(x, y);
+        let mut linear_pixel = Rgba([0.0; 4]);
+        for (linear_channel, channel) in linear_pixel.0.iter_mut().zip(pixel.0.iter()) {
+            *linear_channel = *channel as f32 / 255.0;
+        }
+        if color_space == ColorSpace::Srgb {
+            for channel in &mut linear_pixel.0[..3] {
+                *channel = srgb_to_linear(*channel);
+            }
+        }
+        linear_pixel
+    })
+}
+
+fn from_linear(image: &LinearImage, color_space: ColorSpace) -> RgbaImage {
+    ImageBuffer::from_fn(image.width(), image.height(), |x, y| {
+        let mut linear_pixel = *image.get_pixel(x, y);
+        if color_space == ColorSpace::Srgb {
+            for channel in &mut linear_pixel.0[..3] {
+                *channel = linear_to_srgb(*channel);
+            }
+        }
+        let mut pixel = Rgba([0; 4]);
+        for (channel, linear_channel) in pixel.0.iter_mut().zip(linear_pixel.0.iter()) {
+            *channel = (linear_channel.clamp(0.0, 1.0) * 255.0).round() as u8;
+        }
+        pixel
+    })
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    }
+    else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    }
+    else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn convert_to_linear_and_back() {
+        let image = RgbaImage::from_fn(16, 1, |x, _| Rgba([(x * 17) as u8, 255 - (x * 17) as u8, 128, (x * 16) as u8]));
+        for color_space in [ColorSpace::Srgb, ColorSpace::Linear].iter() {
+            assert_eq!(from_linear(&to_linear(&image, *color_space), *color_space), image);
+        }
+    }
+
+    #[test]
+    fn downsample_in_linear_space() {
+        let image = RgbaImage::from_fn(2, 1, |x, _| if x == 0 { Rgba([0, 0, 0, 255]) } else { Rgba([255, 255, 255, 255]) });
+        let linear_image = image::imageops::resize(&to_linear(&image, ColorSpace::Srgb), 1, 1, image::imageops::FilterType::Triangle);
+        // Half of the light is 188 in sRGB, not 128.
+        assert_eq!(from_linear(&linear_image, ColorSpace::Srgb).get_pixel(0, 0), &Rgba([188, 188, 188, 255]));
+    }
+}
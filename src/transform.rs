@@ -25,6 +25,22 @@ impl Transform2D {
         )
     }
 
+    pub fn inverse_transform_point(&self, point: glam::Vec2) -> glam::Vec2 {
+        let delta = point - self.translation.truncate();
+        let (sin, cos) = (-self.rotation.to_radians()).sin_cos();
+        glam::Vec2::new(delta.x * cos - delta.y * sin, delta.x * sin + delta.y * cos)
+    }
+
+    // Inverse of `multiply`, returns the transform that results in `transform` when multiplied with `self`.
+    pub fn relative_transform(&self, transform: &Transform2D) -> Self {
+        let rotation = transform.rotation - self.rotation;
+        let delta = transform.translation - self.translation;
+        let (sin, cos) = (-transform.rotation.to_radians()).sin_cos();
+        let translation = glam::Vec3::new(delta.x * cos - delta.y * sin, delta.x * sin + delta.y * cos, 0.0);
+
+        Self { translation, rotation }
+    }
+
     pub fn multiply(&self, transform: &Transform2D) -> Self {
         let mut rotation = ((self.rotation + transform.rotation) + 180.0) % 360.0;
         if rotation < 0.0 {
@@ -59,24 +75,26 @@ pub fn propagate_transforms(world: &mut World, resources: &mut Resources) {
 
     let root_entities = app_state.root_entities.clone();
     for entity in &root_entities {
-        let mut entry = world.entry(*entity).unwrap();
-        let local_transform = entry.get_component::<LocalTransform>().unwrap().0.clone();
-        let global_transform = entry.get_component_mut::<GlobalTransform>().unwrap();
-        global_transform.0 = local_transform.clone();
-        if let Ok(children) = entry.get_component::<Children>() {
-            let children = children.0.clone();
-            for child in children {
-                propagate_recursive(child, local_transform.clone(), world);
-            }
-        }
+        propagate_recursive(*entity, None, world);
     }
 }
 
-fn propagate_recursive(entity: Entity, parent_transform: Transform2D, world: &mut World) {
-    let mut entry = world.entry(entity).unwrap();
-    let transform = parent_transform.multiply(&entry.get_component::<LocalTransform>().unwrap().0);
-    let global_transform = entry.get_component_mut::<GlobalTransform>().unwrap();
-    global_transform.0 = transform.clone();
+// Entities without a transform don't move their children, so they get the transform of their parent.
+fn propagate_recursive(entity: Entity, parent_transform: Option<Transform2D>, world: &mut World) {
+    let mut entry = match world.entry(entity) {
+        Some(entry) => entry,
+        None => return,
+    };
+    let transform = match entry.get_component::<LocalTransform>() {
+        Ok(local_transform) => match &parent_transform {
+            Some(parent_transform) => Some(parent_transform.multiply(&local_transform.0)),
+            None => Some(local_transform.0.clone()),
+        },
+        Err(_) => parent_transform,
+    };
+    if let (Some(transform), Ok(global_transform)) = (&transform, entry.get_component_mut::<GlobalTransform>()) {
+        global_transform.0 = transform.clone();
+    }
 
     if let Ok(children) = entry.get_component::<Children>() {
         let children = children.0.clone();
@@ -85,3 +103,62 @@ fn propagate_recursive(entity: Entity, parent_transform: Transform2D, world: &mu
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smallvec::smallvec;
+
+    fn transform(x: f32, y: f32, rotation: f32) -> Transform2D {
+        Transform2D::new(&glam::Vec3::new(x, y, 0.0), rotation)
+    }
+
+    fn assert_close(a: &Transform2D, b: &Transform2D) {
+        let rotation_difference = (a.rotation - b.rotation + 180.0).rem_euclid(360.0) - 180.0;
+        assert!(rotation_difference.abs() < 1e-3, "{:?} != {:?}", a, b);
+        assert!((a.translation - b.translation).length() < 1e-3, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn relative_transform_is_inverse_of_multiply() {
+        let parents = [transform(0.0, 0.0, 0.0), transform(10.0, -5.0, 90.0), transform(-3.0, 7.0, -135.0)];
+        let children = [transform(0.0, 0.0, 0.0), transform(4.0, 2.0, 30.0), transform(-8.0, 1.0, 170.0)];
+        for parent in &parents {
+            for child in &children {
+                let global = parent.multiply(child);
+                assert_close(&parent.relative_transform(&global), child);
+                assert_close(&parent.multiply(&parent.relative_transform(child)), child);
+            }
+        }
+    }
+
+    #[test]
+    fn multiply_wraps_rotation() {
+        let result = transform(0.0, 0.0, 170.0).multiply(&transform(0.0, 0.0, 20.0));
+        assert!((result.rotation - -170.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn inverse_transform_point() {
+        let point = transform(10.0, 0.0, 90.0).inverse_transform_point(glam::Vec2::new(10.0, 5.0));
+        assert!((point - glam::Vec2::new(5.0, 0.0)).length() < 1e-3);
+    }
+
+    #[test]
+    fn propagate_through_entities_without_transform() {
+        let mut world = World::default();
+        let child_transform = transform(1.0, 0.0, 0.0);
+        let child = world.push((LocalTransform(child_transform.clone()), GlobalTransform(child_transform)));
+        let group = world.push((Children(smallvec![child]),));
+        let root_transform = transform(10.0, 20.0, 0.0);
+        let root = world.push((
+            LocalTransform(root_transform.clone()),
+            GlobalTransform(root_transform),
+            Children(smallvec![group]),
+        ));
+
+        propagate_recursive(root, None, &mut world);
+        let global_transform = world.entry(child).unwrap().get_component::<GlobalTransform>().unwrap().0.clone();
+        assert_close(&global_transform, &transform(11.0, 20.0, 0.0));
+    }
+}
@@ -0,0 +1,276 @@
+use std::collections::HashMap;
+use imgui::*;
+use crate::{
+    app_state::AppState,
+    events::Events,
+    command::Command,
+    history::{Edit, Transaction},
+    static_data::{Collection, Component, DataAccessor},
+};
+
+pub struct Inspector {
+    // Set while a widget keeps changing the same value, so that e.g. dragging only creates one undo step.
+    is_edit_continuing: bool,
+}
+
+impl Inspector {
+    pub fn new() -> Self {
+        Self {
+            is_edit_continuing: false,
+        }
+    }
+
+    pub fn build(&mut self, ui: &Ui, app_state: &AppState, commands: &mut Events<Command>) {
+        let node_index = match app_state.selected_nodes.last() {
+            Some(node_index) => *node_index,
+            None => {
+                ui.text(im_str!("No scene node selected."));
+                return;
+            }
+        };
+        let data_accessor = &app_state.data_accessor;
+        let collection = &data_accessor.collections[node_index.0];
+        let node = &collection.scene_nodes[node_index.1];
+
+        ui.text(im_str!("Name: {}", node.name));
+        if !node.prefab.is_empty() {
+            ui.text(im_str!("Prefab: {}", node.prefab));
+        }
+        if app_state.selected_nodes.len() > 1 {
+            ui.text(im_str!("{} scene nodes selected", app_state.selected_nodes.len()));
+        }
+        ui.separator();
+
+        let mut transactions = Vec::new();
+        for component in &node.components {
+            let id_token = ui.push_id(component_label(component));
+            match component {
+                Component::Transform { translation, rotation } => {
+                    if CollapsingHeader::new(im_str!("Transform")).default_open(true).build(ui) {
+                        let mut new_translation = [translation.0, translation.1];
+                        let mut new_rotation = *rotation;
+                        let mut changed = ui.input_float2(im_str!("Translation"), &mut new_translation).build();
+                        changed |= ui.input_float(im_str!("Rotation"), &mut new_rotation).build();
+                        if changed {
+                            let new_component = Component::Transform {
+                                translation: (new_translation[0], new_translation[1]),
+                                rotation: new_rotation,
+                            };
+                            let edit = Edit::set_component(data_accessor, node_index, new_component);
+                            let mut transaction = Transaction::new("Edit Transform", vec![edit]);
+                            if self.is_edit_continuing {
+                                transaction = transaction.merged();
+                            }
+                            transactions.push(transaction);
+                            self.is_edit_continuing = true;
+                        }
+                    }
+                }
+                Component::Parent(name) => {
+                    if CollapsingHeader::new(im_str!("Parent")).default_open(true).build(ui) {
+                        let names: Vec<String> = collection.scene_nodes.iter()
+                            .filter(|scene_node| can_be_parent(collection, &scene_node.name, &node.name))
+                            .map(|scene_node| scene_node.name.clone())
+                            .collect();
+                        if let Some(new_name) = reference_combo(ui, im_str!("Node"), name, &names) {
+                            let edit = Edit::set_component(data_accessor, node_index, Component::Parent(new_name));
+                            transactions.push(Transaction::new("Set Parent", vec![edit]));
+                        }
+                    }
+                }
+                Component::Mesh(name) => {
+                    if CollapsingHeader::new(im_str!("Mesh")).default_open(true).build(ui) {
+                        let names = reference_names(data_accessor, node_index.0, |collection| &collection.mesh_indices);
+                        if let Some(new_name) = reference_combo(ui, im_str!("Mesh"), name, &names) {
+                            let edit = Edit::set_component(data_accessor, node_index, Component::Mesh(new_name));
+                            transactions.push(Transaction::new("Set Mesh", vec![edit]));
+                        }
+                    }
+                }
+                Component::RigidBody(name) => {
+                    if CollapsingHeader::new(im_str!("Rigid Body")).default_open(true).build(ui) {
+                        let names = reference_names(data_accessor, node_index.0, |collection| &collection.rigid_body_indices);
+                        if let Some(new_name) = reference_combo(ui, im_str!("Rigid Body"), name, &names) {
+                            let edit = Edit::set_component(data_accessor, node_index, Component::RigidBody(new_name));
+                            transactions.push(Transaction::new("Set Rigid Body", vec![edit]));
+                        }
+                    }
+                }
+                Component::Collider(name) => {
+                    if CollapsingHeader::new(im_str!("Collider")).default_open(true).build(ui) {
+                        let names = reference_names(data_accessor, node_index.0, |collection| &collection.collider_indices);
+                        if let Some(new_name) = reference_combo(ui, im_str!("Collider"), name, &names) {
+                            let edit = Edit::set_component(data_accessor, node_index, Component::Collider(new_name));
+                            transactions.push(Transaction::new("Set Collider", vec![edit]));
+                        }
+                    }
+                }
+                Component::Color(r, g, b, a) => {
+                    if CollapsingHeader::new(im_str!("Color")).default_open(true).build(ui) {
+                        let mut color = [*r, *g, *b, *a];
+                        if ColorEdit::new(im_str!("Tint"), &mut color).alpha_bar(true).build(ui) {
+                            let new_component = Component::Color(color[0], color[1], color[2], color[3]);
+                            let edit = Edit::set_component(data_accessor, node_index, new_component);
+                            let mut transaction = Transaction::new("Edit Color", vec![edit]);
+                            if self.is_edit_continuing {
+                                transaction = transaction.merged();
+                            }
+                            transactions.push(transaction);
+                            self.is_edit_continuing = true;
+                        }
+                    }
+                }
+                Component::Layer { name, order } => {
+                    if CollapsingHeader::new(im_str!("Layer")).default_open(true).build(ui) {
+                        // The default layer has an empty name.
+                        let mut labels = vec![ImString::new("Default")];
+                        labels.extend(collection.render_layers.iter().map(|layer| ImString::new(layer.clone())));
+                        let mut selected = collection.get_render_layer_index(name).unwrap_or_else(|| {
+                            labels.push(ImString::new(format!("{} (missing)", name)));
+                            labels.len() - 1
+                        });
+                        let label_refs: Vec<&ImString> = labels.iter().collect();
+                        let mut new_name = name.clone();
+                        let mut new_order = *order;
+                        let mut changed = false;
+                        if ComboBox::new(im_str!("Layer")).build_simple_string(ui, &mut selected, &label_refs) {
+                            if selected == 0 {
+                                new_name = String::new();
+                                changed = true;
+                            }
+                            else if let Some(layer_name) = collection.render_layers.get(selected - 1) {
+                                new_name = layer_name.clone();
+                                changed = true;
+                            }
+                        }
+                        changed |= ui.input_int(im_str!("Order"), &mut new_order).build();
+                        if changed {
+                            let new_component = Component::Layer { name: new_name, order: new_order };
+                            let edit = Edit::set_component(data_accessor, node_index, new_component);
+                            transactions.push(Transaction::new("Set Layer", vec![edit]));
+                        }
+                    }
+                }
+            }
+
+            // Meshes and physics need a transform, so it can't be removed.
+            let is_removable = !matches!(component, Component::Transform { .. });
+            if is_removable && ui.small_button(im_str!("Remove")) {
+                let edit = Edit::remove_component(data_accessor, node_index, component);
+                transactions.push(Transaction::new(&format!("Remove {}", component_label(component)), vec![edit]));
+            }
+            id_token.pop(ui);
+        }
+
+        ui.separator();
+        let addable_components = addable_components(data_accessor, node_index);
+        if !addable_components.is_empty() {
+            let mut labels = vec![ImString::new("Add Component...")];
+            labels.extend(addable_components.iter().map(|component| ImString::new(component_label(component))));
+            let label_refs: Vec<&ImString> = labels.iter().collect();
+            let mut selected = 0;
+            if ComboBox::new(im_str!("##add_component")).build_simple_string(ui, &mut selected, &label_refs) && selected > 0 {
+                let component = addable_components[selected - 1].clone();
+                let label = component_label(&component);
+                let edit = Edit::set_component(data_accessor, node_index, component);
+                transactions.push(Transaction::new(&format!("Add {}", label), vec![edit]));
+            }
+        }
+
+        if !ui.is_any_item_active() {
+            self.is_edit_continuing = false;
+        }
+
+        for transaction in transactions {
+            commands.send(Command::Execute(transaction));
+        }
+    }
+}
+
+fn component_label(component: &Component) -> &'static str {
+    match component {
+        Component::Transform { .. } => "Transform",
+        Component::Parent(_) => "Parent",
+        Component::Mesh(_) => "Mesh",
+        Component::RigidBody(_) => "Rigid Body",
+        Component::Collider(_) => "Collider",
+        Component::Layer { .. } => "Layer",
+        Component::Color(..) => "Color",
+    }
+}
+
+// Names that can be referenced from the given collection. Names from other collections are prefixed with the
+// collection name.
+fn reference_names<F>(data_accessor: &DataAccessor, collection_index: usize, get_indices: F) -> Vec<String>
+where
+    F: Fn(&Collection) -> &HashMap<String, usize>,
+{
+    let mut names = Vec::new();
+    for (i, collection) in data_accessor.collections.iter().enumerate() {
+        let mut collection_names: Vec<(&String, &usize)> = get_indices(collection).iter().collect();
+        collection_names.sort_by_key(|(_, index)| **index);
+        for (name, _) in collection_names {
+            if i == collection_index {
+                names.push(name.clone());
+            }
+            else {
+                names.push(format!("{}/{}", collection.name, name));
+            }
+        }
+    }
+    names
+}
+
+fn reference_combo(ui: &Ui, label: &ImStr, current_name: &String, names: &[String]) -> Option<String> {
+    let mut labels: Vec<ImString> = names.iter().map(|name| ImString::new(name.clone())).collect();
+    let mut selected = match names.iter().position(|name| name == current_name) {
+        Some(index) => index,
+        None => {
+            labels.push(ImString::new(format!("{} (missing)", current_name)));
+            labels.len() - 1
+        }
+    };
+    let label_refs: Vec<&ImString> = labels.iter().collect();
+    if ComboBox::new(label).build_simple_string(ui, &mut selected, &label_refs) && selected < names.len() {
+        return Some(names[selected].clone());
+    }
+    None
+}
+
+// Descendants can't be parents, since the nodes of a cycle would never be spawned.
+fn can_be_parent(collection: &Collection, parent_name: &str, node_name: &str) -> bool {
+    parent_name != node_name && !collection.is_descendant(parent_name, node_name)
+}
+
+fn addable_components(data_accessor: &DataAccessor, node_index: (usize, usize)) -> Vec<Component> {
+    let collection = &data_accessor.collections[node_index.0];
+    let node = &collection.scene_nodes[node_index.1];
+    let first_name = |names: Vec<String>| names.into_iter().next();
+
+    let mut candidates = vec![Some(Component::Transform { translation: (0.0, 0.0), rotation: 0.0 })];
+    candidates.push(
+        collection.scene_nodes.iter()
+            .find(|scene_node| can_be_parent(collection, &scene_node.name, &node.name))
+            .map(|scene_node| Component::Parent(scene_node.name.clone()))
+    );
+    candidates.push(
+        first_name(reference_names(data_accessor, node_index.0, |collection| &collection.mesh_indices))
+            .map(Component::Mesh)
+    );
+    candidates.push(
+        first_name(reference_names(data_accessor, node_index.0, |collection| &collection.rigid_body_indices))
+            .map(Component::RigidBody)
+    );
+    candidates.push(
+        first_name(reference_names(data_accessor, node_index.0, |collection| &collection.collider_indices))
+            .map(Component::Collider)
+    );
+
+    candidates.push(Some(Component::Layer { name: String::new(), order: 0 }));
+    candidates.push(Some(Component::Color(1.0, 1.0, 1.0, 1.0)));
+
+    candidates.into_iter()
+        .flatten()
+        .filter(|component| node.get_component(component).is_none())
+        .collect()
+}
@@ -1,3 +1,7 @@
 mod ui_state;
+mod inspector;
+mod viewport;
 
 pub use ui_state::*;
+pub use inspector::Inspector;
+pub use viewport::build_viewport;
@@ -1,4 +1,7 @@
-use std::time::Duration;
+use std::{
+    iter,
+    time::Duration,
+};
 use imgui_winit_support::WinitPlatform;
 use legion::*;
 use winit::window::Window;
@@ -9,14 +12,26 @@ use crate::{
     app_state::AppState,
     application::DeltaTime,
     events::Events,
-    command::Command,
+    command::{Command, SelectionMode},
+    camera::Camera,
+    gizmo::Gizmo,
+    physics::{Simulation, SimulationMode},
+    ui::{Inspector, build_viewport},
 };
 
+// Whether imgui wants the input of the last frame, so the viewport can ignore it.
+#[derive(Default)]
+pub struct UiFocus {
+    pub wants_mouse: bool,
+    pub wants_keyboard: bool,
+}
+
 pub struct UiState {
     pub imgui: Context,
     pub platform: WinitPlatform,
     renderer: Renderer,
     last_cursor: Option<MouseCursor>,
+    inspector: Inspector,
 }
 
 impl UiState {
@@ -56,6 +71,7 @@ impl UiState {
             platform,
             renderer,
             last_cursor: None,
+            inspector: Inspector::new(),
         }
     }
 
@@ -64,6 +80,9 @@ impl UiState {
         let wgpu_state = resources.get::<WgpuState>().unwrap();
         let app_state = resources.get::<AppState>().unwrap();
         let mut commands = resources.get_mut::<Events<Command>>().unwrap();
+        let camera = resources.get::<Camera>().unwrap();
+        let mut gizmo = resources.get_mut::<Gizmo>().unwrap();
+        let simulation = resources.get::<Simulation>().unwrap();
 
         if let None = wgpu_state.current_frame { return; }
         let frame = &wgpu_state.current_frame.as_ref().unwrap().output;
@@ -75,6 +94,14 @@ impl UiState {
             .expect("Failed to prepare frame");
         let ui = self.imgui.frame();
 
+        let selection_mode = if ui.io().key_ctrl {
+            SelectionMode::Toggle
+        } else if ui.io().key_shift {
+            SelectionMode::Add
+        } else {
+            SelectionMode::Replace
+        };
+
         {
             let mut current_collection = app_state.current_collection;
 
@@ -99,23 +126,65 @@ impl UiState {
                                 let result = unsafe { ImStr::from_utf8_with_nul_unchecked(item.as_bytes()).into() };
                                 return result;
                             });
-                        /*ui.separator();
-                        let mouse_pos = ui.io().mouse_pos;
-                        ui.text(im_str!("Mouse Position: ({:.1},{:.1})", mouse_pos[0], mouse_pos[1]));*/
+                        if ui.button(im_str!("Save"), [0.0, 0.0]) {
+                            commands.send(Command::SaveCollection(current_collection));
+                        }
+                        if app_state.history.can_undo() {
+                            ui.same_line(0.0);
+                            if ui.button(im_str!("Undo"), [0.0, 0.0]) {
+                                commands.send(Command::Undo);
+                            }
+                        }
+                        if app_state.history.can_redo() {
+                            ui.same_line(0.0);
+                            if ui.button(im_str!("Redo"), [0.0, 0.0]) {
+                                commands.send(Command::Redo);
+                            }
+                        }
+                        ui.separator();
+                        let cursor_position = camera.cursor_world_position();
+                        ui.text(im_str!("Cursor: ({:.1}, {:.1})", cursor_position.x, cursor_position.y));
+                        ui.text(im_str!("Zoom: {:.2}", camera.zoom));
+                    }
+                    if CollapsingHeader::new(im_str!("Layers"))
+                        .default_open(true)
+                        .build(&ui) {
+                        if let Some(collection) = app_state.data_accessor.collections.get(current_collection) {
+                            // Listed from top to bottom.
+                            let layer_names: Vec<&str> = iter::once("Default")
+                                .chain(collection.render_layers.iter().map(String::as_str))
+                                .collect();
+                            for (layer, layer_name) in layer_names.into_iter().enumerate().rev() {
+                                let id_token = ui.push_id(layer as i32);
+                                let mut is_visible = !app_state.hidden_layers.contains(&layer);
+                                if ui.checkbox(im_str!("Visible"), &mut is_visible) {
+                                    commands.send(Command::SetLayerHidden(layer, !is_visible));
+                                }
+                                ui.same_line(0.0);
+                                let mut is_locked = app_state.locked_layers.contains(&layer);
+                                if ui.checkbox(im_str!("Locked"), &mut is_locked) {
+                                    commands.send(Command::SetLayerLocked(layer, is_locked));
+                                }
+                                ui.same_line(0.0);
+                                ui.text(layer_name);
+                                id_token.pop(&ui);
+                            }
+                        }
                     }
                     if CollapsingHeader::new(im_str!("Scene Nodes"))
                         .default_open(true)
                         .build(&ui) {
 
-                        let collection = &app_state.data_accessor.collections[current_collection];
-                        for i in 0..collection.scene_nodes.len() {
-                            let is_root = if let None = collection.scene_nodes[i].get_parent() {
-                                true
-                            } else {
-                                false
-                            };
-                            if is_root {
-                                Self::add_tree_nodes_recursive((current_collection, i), &app_state, &ui);
+                        if let Some(collection) = app_state.data_accessor.collections.get(current_collection) {
+                            for i in 0..collection.scene_nodes.len() {
+                                let is_root = if let None = collection.scene_nodes[i].get_parent() {
+                                    true
+                                } else {
+                                    false
+                                };
+                                if is_root {
+                                    Self::add_tree_nodes_recursive((current_collection, i), &app_state, &ui, selection_mode, &mut commands);
+                                }
                             }
                         }
                     }
@@ -125,6 +194,80 @@ impl UiState {
             if app_state.current_collection != current_collection {
                 commands.send(Command::SetCurrentScene(current_collection));
             }
+
+            let inspector = &mut self.inspector;
+            imgui::Window::new(im_str!("Inspector"))
+                .position([size.width - 300.0, 0.0], Condition::FirstUseEver)
+                .size([300.0, size.height], Condition::FirstUseEver)
+                .build(&ui, || {
+                    inspector.build(&ui, &app_state, &mut commands);
+                });
+
+            imgui::Window::new(im_str!("Simulation"))
+                .position([300.0, 0.0], Condition::FirstUseEver)
+                .always_auto_resize(true)
+                .build(&ui, || {
+                    if simulation.mode == SimulationMode::Playing {
+                        if ui.button(im_str!("Pause"), [0.0, 0.0]) {
+                            commands.send(Command::PauseSimulation);
+                        }
+                    }
+                    else if ui.button(im_str!("Play"), [0.0, 0.0]) {
+                        commands.send(Command::PlaySimulation);
+                    }
+                    ui.same_line(0.0);
+                    if ui.button(im_str!("Step"), [0.0, 0.0]) {
+                        commands.send(Command::StepSimulation);
+                    }
+                    if simulation.mode != SimulationMode::Edit {
+                        ui.same_line(0.0);
+                        if ui.button(im_str!("Reset"), [0.0, 0.0]) {
+                            commands.send(Command::ResetSimulation);
+                        }
+                    }
+                    ui.same_line(0.0);
+                    let mode = match simulation.mode {
+                        SimulationMode::Edit => "Editing",
+                        SimulationMode::Playing => "Playing",
+                        SimulationMode::Paused => "Paused",
+                    };
+                    ui.text(mode);
+                });
+
+            let problems = app_state.data_accessor.problems.iter().chain(app_state.scene_problems.iter());
+            let num_problems = app_state.data_accessor.problems.len() + app_state.scene_problems.len();
+            imgui::Window::new(&im_str!("Problems ({})###Problems", num_problems))
+                .position([300.0, size.height - 200.0], Condition::FirstUseEver)
+                .size([size.width - 600.0, 200.0], Condition::FirstUseEver)
+                .build(&ui, || {
+                    if ui.button(im_str!("Validate"), [0.0, 0.0]) {
+                        commands.send(Command::ValidateCollections);
+                    }
+                    if num_problems == 0 {
+                        ui.text(im_str!("No problems found."));
+                    }
+                    for problem in problems {
+                        ui.text_wrapped(&im_str!("{}", problem));
+                    }
+                    if let Some(report) = &app_state.validation_report {
+                        if CollapsingHeader::new(im_str!("Validation")).default_open(true).build(&ui) {
+                            if report.is_ok() {
+                                ui.text(im_str!("All references are valid."));
+                            }
+                            for problem in &report.problems {
+                                ui.text_wrapped(&im_str!("{}", problem));
+                            }
+                        }
+                    }
+                });
+        }
+
+        build_viewport(&ui, &mut gizmo, &camera, &app_state, selection_mode, &mut commands);
+
+        {
+            let mut ui_focus = resources.get_mut::<UiFocus>().unwrap();
+            ui_focus.wants_mouse = ui.io().want_capture_mouse;
+            ui_focus.wants_keyboard = ui.io().want_capture_keyboard;
         }
 
         let mut encoder = wgpu_state
@@ -158,28 +301,34 @@ impl UiState {
         wgpu_state.queue.submit(Some(encoder.finish()));
     }
 
-    fn add_tree_nodes_recursive(scene_node: (usize, usize), app_state: &AppState, ui: &Ui) {
+    fn add_tree_nodes_recursive(
+        scene_node: (usize, usize),
+        app_state: &AppState,
+        ui: &Ui,
+        selection_mode: SelectionMode,
+        commands: &mut Events<Command>,
+    ) {
         let collection = &app_state.data_accessor.collections[scene_node.0];
         let node = &collection.scene_nodes[scene_node.1];
         let mut name = node.name.clone();
         name.push_str("\0");
         let is_leaf = node.children.is_empty();
-        let is_selected = if let Some(selected_node) = app_state.selected_scene {
-            selected_node == scene_node
-        } else { false };
-        imgui::TreeNode::new(unsafe { ImStr::from_utf8_with_nul_unchecked(name.as_bytes().into()) })
+        let is_selected = app_state.selected_nodes.contains(&scene_node);
+        let tree_node_token = imgui::TreeNode::new(unsafe { ImStr::from_utf8_with_nul_unchecked(name.as_bytes()) })
             .default_open(true)
             .open_on_arrow(true)
             .leaf(is_leaf)
             .selected(is_selected)
-            .build(&ui, || {
-                for child in &node.children {
-                    Self::add_tree_nodes_recursive((scene_node.0, *child), app_state, &ui);
-                }
-                /*if ui.is_item_clicked(MouseButton::Left) {
-                    app_state.selected_scene = Some(scene_node);
-                }*/
-            });
+            .push(ui);
+        if ui.is_item_clicked(MouseButton::Left) {
+            commands.send(Command::Select(vec![scene_node], selection_mode));
+        }
+        if let Some(tree_node_token) = tree_node_token {
+            for child in &node.children {
+                Self::add_tree_nodes_recursive((scene_node.0, *child), app_state, ui, selection_mode, commands);
+            }
+            tree_node_token.pop(ui);
+        }
     }
 }
 
@@ -0,0 +1,151 @@
+use imgui::*;
+use crate::{
+    app_state::AppState,
+    camera::Camera,
+    events::Events,
+    command::{Command, SelectionMode},
+    gizmo::{Gizmo, GizmoDrag, GizmoHandle, GizmoMode},
+    history::{Edit, Transaction},
+    static_data::Component,
+};
+
+const AXIS_LENGTH: f32 = 80.0;
+const RING_RADIUS: f32 = 60.0;
+const HANDLE_SIZE: f32 = 8.0;
+
+const COLOR_X: [f32; 4] = [0.9, 0.2, 0.2, 1.0];
+const COLOR_Y: [f32; 4] = [0.2, 0.9, 0.2, 1.0];
+const COLOR_CENTER: [f32; 4] = [0.9, 0.9, 0.2, 1.0];
+const COLOR_ACTIVE: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+// Handles mouse interaction with the mesh viewport, i.e. everything not covered by an imgui window. All positions
+// here are in logical window coordinates, like imgui's.
+pub fn build_viewport(
+    ui: &Ui,
+    gizmo: &mut Gizmo,
+    camera: &Camera,
+    app_state: &AppState,
+    selection_mode: SelectionMode,
+    commands: &mut Events<Command>,
+) {
+    let to_world = |position: [f32; 2]| camera.logical_to_world((position[0], position[1]));
+    let to_screen = |position: glam::Vec2| {
+        let position = camera.world_to_logical(position);
+        [position.0, position.1]
+    };
+    let mouse_pos = ui.io().mouse_pos;
+
+    if let Some(target) = &gizmo.target {
+        let center = to_screen(target.global_transform.translation.truncate());
+        let hovered_handle = get_hovered_handle(gizmo.mode, center, mouse_pos);
+        let active_handle = gizmo.drag.as_ref().map(|drag| drag.handle).or(hovered_handle);
+        let handle_color = |handle: GizmoHandle, color: [f32; 4]| {
+            if active_handle == Some(handle) { COLOR_ACTIVE } else { color }
+        };
+
+        let draw_list = ui.get_background_draw_list();
+        match gizmo.mode {
+            GizmoMode::Translate => {
+                let x_end = [center[0] + AXIS_LENGTH, center[1]];
+                let y_end = [center[0], center[1] - AXIS_LENGTH];
+                let x_color = handle_color(GizmoHandle::AxisX, COLOR_X);
+                let y_color = handle_color(GizmoHandle::AxisY, COLOR_Y);
+                draw_list.add_line(center, x_end, x_color).thickness(2.0).build();
+                draw_list.add_triangle(
+                    [x_end[0] + HANDLE_SIZE, x_end[1]],
+                    [x_end[0], x_end[1] - HANDLE_SIZE * 0.5],
+                    [x_end[0], x_end[1] + HANDLE_SIZE * 0.5],
+                    x_color,
+                ).filled(true).build();
+                draw_list.add_line(center, y_end, y_color).thickness(2.0).build();
+                draw_list.add_triangle(
+                    [y_end[0], y_end[1] - HANDLE_SIZE],
+                    [y_end[0] - HANDLE_SIZE * 0.5, y_end[1]],
+                    [y_end[0] + HANDLE_SIZE * 0.5, y_end[1]],
+                    y_color,
+                ).filled(true).build();
+                draw_list.add_rect(
+                    [center[0] - HANDLE_SIZE * 0.5, center[1] - HANDLE_SIZE * 0.5],
+                    [center[0] + HANDLE_SIZE * 0.5, center[1] + HANDLE_SIZE * 0.5],
+                    handle_color(GizmoHandle::Center, COLOR_CENTER),
+                ).filled(true).build();
+            }
+            GizmoMode::Rotate => {
+                let ring_color = handle_color(GizmoHandle::Ring, COLOR_CENTER);
+                let (sin, cos) = target.global_transform.rotation.to_radians().sin_cos();
+                draw_list.add_circle(center, RING_RADIUS, ring_color).num_segments(48).thickness(2.0).build();
+                draw_list.add_line(center, [center[0] + cos * RING_RADIUS, center[1] - sin * RING_RADIUS], ring_color).build();
+            }
+        }
+
+        if !ui.io().want_capture_mouse && ui.is_mouse_clicked(MouseButton::Left) {
+            if let Some(handle) = hovered_handle {
+                gizmo.drag = Some(GizmoDrag {
+                    handle,
+                    target: target.clone(),
+                    start_position: to_world(mouse_pos),
+                    has_edited: false,
+                });
+                return;
+            }
+        }
+    }
+
+    if gizmo.drag.is_some() {
+        if !ui.is_mouse_down(MouseButton::Left) {
+            gizmo.drag = None;
+        }
+        else if ui.io().mouse_delta != [0.0, 0.0] {
+            let transform = gizmo.drag_to(to_world(mouse_pos));
+            if let (Some(drag), Some(transform)) = (&mut gizmo.drag, transform) {
+                let new_component = Component::Transform {
+                    translation: (transform.translation.x, transform.translation.y),
+                    rotation: transform.rotation,
+                };
+                let edit = Edit::set_component(&app_state.data_accessor, drag.target.node, new_component);
+                let name = match drag.handle {
+                    GizmoHandle::Ring => "Rotate",
+                    _ => "Move",
+                };
+                // All edits of one drag are merged into a single transaction.
+                let mut transaction = Transaction::new(name, vec![edit]);
+                if drag.has_edited {
+                    transaction = transaction.merged();
+                }
+                drag.has_edited = true;
+                commands.send(Command::Execute(transaction));
+            }
+        }
+        return;
+    }
+
+    if !ui.io().want_capture_mouse && ui.is_mouse_clicked(MouseButton::Left) {
+        commands.send(Command::Pick(to_world(mouse_pos), selection_mode));
+    }
+}
+
+fn get_hovered_handle(mode: GizmoMode, center: [f32; 2], mouse_pos: [f32; 2]) -> Option<GizmoHandle> {
+    let dx = mouse_pos[0] - center[0];
+    let dy = center[1] - mouse_pos[1];
+    match mode {
+        GizmoMode::Translate => {
+            if dx.abs() <= HANDLE_SIZE && dy.abs() <= HANDLE_SIZE {
+                Some(GizmoHandle::Center)
+            } else if dx > 0.0 && dx <= AXIS_LENGTH + HANDLE_SIZE && dy.abs() <= HANDLE_SIZE {
+                Some(GizmoHandle::AxisX)
+            } else if dy > 0.0 && dy <= AXIS_LENGTH + HANDLE_SIZE && dx.abs() <= HANDLE_SIZE {
+                Some(GizmoHandle::AxisY)
+            } else {
+                None
+            }
+        }
+        GizmoMode::Rotate => {
+            let distance = (dx * dx + dy * dy).sqrt();
+            if (distance - RING_RADIUS).abs() <= HANDLE_SIZE {
+                Some(GizmoHandle::Ring)
+            } else {
+                None
+            }
+        }
+    }
+}